@@ -0,0 +1,67 @@
+// benches/scan.rs
+//
+// Benchmark of the scanner on big (multi-megabyte) Lox programs.
+//
+// Run with:   cargo bench --bench scan
+//
+// The time per byte should stay about the same as the input gets bigger.
+// If it grows with the size of the input, something in the scanner has gone
+// quadratic again.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rublox::tokenize::tokenize;
+
+// A chunk of Lox code with a bit of everything in it (including non-ASCII text)
+const CHUNK : &str = r#"// Computing some totals.  Ünïcödé in comments is fine
+var total = 0;
+var n = 0;
+while n < 100000 {
+    total = total + n * 2.5;
+    n = n + 1;
+}
+var café = "naïve ☃ \"quoted\"\n";
+if total >= 1234.5678 {
+    print café + "yes";
+} else {
+    print !(n != 3) == false;
+}
+"#;
+
+// Build a program of at least the given size by repeating the chunk
+fn make_source(size : usize) -> String {
+    let mut src = String::with_capacity(size + CHUNK.len());
+    while src.len() < size {
+    src.push_str(CHUNK);
+    }
+    src
+}
+
+// Best of several runs (to filter out noise)
+fn time_tokenize(src : &String) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut ntokens = 0;
+    for _ in 0..5 {
+    let start = Instant::now();
+    let toks = tokenize(black_box(src)).expect("lexical error");
+    best = best.min(start.elapsed());
+    ntokens = toks.len();
+    }
+    (best, ntokens)
+}
+
+fn main() {
+    const MB : usize = 1024 * 1024;
+    for size in [MB, 2*MB, 4*MB, 8*MB] {
+    let src = make_source(size);
+    let (elapsed, ntokens) = time_tokenize(&src);
+    let secs = elapsed.as_secs_f64();
+    println!("{:>5.1} MB  {:>9} tokens  {:>8.2} ms  {:>7.1} MB/s  {:>5.2} ns/byte",
+         src.len() as f64 / MB as f64,
+         ntokens,
+         secs * 1000.0,
+         src.len() as f64 / MB as f64 / secs,
+         secs * 1e9 / src.len() as f64);
+    }
+}
\ No newline at end of file
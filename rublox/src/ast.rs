@@ -3,9 +3,13 @@
 // Abstract Syntax Tree (AST) for Lox.
 
 use std::fmt;
+use std::rc::Rc;
+
+use crate::Span;
+use crate::parse::Dialect;
 
 // All of the valid operators
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Op {
     OpPlus,       // +
     OpMinus,      // -
@@ -18,6 +22,8 @@ pub enum Op {
     OpEq,         // ==
     OpNe,         // !=
     OpNot,        // !
+    OpAnd,        // and
+    OpOr,         // or
 }
 
 // This allows the Op enum to be converted into a nice string for printing, formatting, etc.
@@ -35,47 +41,388 @@ impl fmt::Display for Op {
 	    OpEq => write!(f, "=="),
 	    OpNe => write!(f, "!="),
 	    OpNot => write!(f, "!"),
+	    OpAnd => write!(f, "and"),
+	    OpOr => write!(f, "or"),
 	}
     }
 }
 
-#[derive(PartialEq, Debug)]
+// Discussion: Every node in the tree needs to know where it came from in the
+// source so that errors (and other tools) can point at it.  Rather than
+// adding a span to every single enum variant, nodes are wrapped up in
+// Spanned<T> which pairs the node with its span.
+//
+// Spans are deliberately ignored when comparing nodes with ==.  Two trees
+// are "the same" if they have the same structure, no matter where they
+// were parsed from (or if they were built by hand with no source at all).
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node : T,
+    pub span : Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node : T, span : Span) -> Spanned<T> {
+	Spanned { node, span }
+    }
+}
+
+impl<T : PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Spanned<T>) -> bool {
+	self.node == other.node
+    }
+}
+
+// Allow a bare node to be compared against a spanned one (handy in tests)
+impl<T : PartialEq> PartialEq<T> for Spanned<T> {
+    fn eq(&self, other: &T) -> bool {
+	self.node == *other
+    }
+}
+
+// A node built by hand (not from source) has an empty span
+impl<T> From<T> for Spanned<T> {
+    fn from(node : T) -> Spanned<T> {
+	Spanned::new(node, Span::default())
+    }
+}
+
+pub type Expr = Spanned<Expression>;
+pub type Stmt = Spanned<Statement>;
+
+#[derive(PartialEq, Clone, Debug)]
 pub enum Expression {
     ENumber(f64),       // A number like 123 or 123.45
     EString(String),    // A string like "hello"
     EBoolean(bool),     // A boolean like true or false
     ENil,               // nil
-    EBinary(Op, Box<Expression>, Box<Expression>),   // expr + expr
-    EUnary(Op, Box<Expression>),                     // -expr
-    EGroup(Box<Expression>),                         // ( expr )
+    EBinary(Op, Box<Expr>, Box<Expr>),   // expr + expr
+    ELogical(Op, Box<Expr>, Box<Expr>),  // expr and expr, expr or expr
+    EUnary(Op, Box<Expr>),               // -expr
+    EGroup(Box<Expr>),                   // ( expr )
     EName(String),      // A variable name
+    EInterpolate(Vec<(String, Expr)>, String),  // "text ${expr} text ${expr} text"
+    ECall(Box<Expr>, Vec<Expr>),         // expr(arg, arg, ...)
+    EGet(Box<Expr>, String),             // expr.name
+    EThis,                               // this
+    ESuper(String),                      // super.name
+    EAssign(Box<Expr>, Box<Expr>),       // target = value  (target is always EName or EGet)
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum Statement {
-    SPrint(Expression),        // print expr ;
-    SVar(String, Expression),  // var name = value;
-    SExpr(Expression),         // expr ;   (Statement expression)
-    SIf(Expression, Box<Statement>, Box<Statement>),
-    SWhile(Expression, Box<Statement>),
-    SAssignment(Expression, Expression),   // location = value ;
-    SBlock(Vec<Statement>),
+    SPrint(Expr),        // print expr ;
+    SVar(String, Expr),  // var name = value;
+    SExpr(Expr),         // expr ;   (Statement expression)
+    SIf(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    SWhile(Expr, Box<Stmt>, Option<Box<Stmt>>),   // while test body  (plus the increment of a for-loop)
+    SBlock(Statements),
+    SFunction(Rc<Function>),   // fun name(params) { body }
+    SReturn(Option<Expr>),     // return [ value ] ;
+    SClass(Class),             // class name [ < superclass ] { methods }
+    SBreak,                    // break ;
+    SContinue,                 // continue ;
 }
 
-pub type Statements = Vec<Statement>;
+pub type Statements = Vec<Stmt>;
+
+// A function definition.  It's in an Rc because the function values that
+// get made from it at runtime need to hang on to it too.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Function {
+    pub name : String,
+    pub params : Vec<String>,
+    pub body : Statements,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Class {
+    pub name : String,
+    pub superclass : Option<Expr>,      // Always an EName
+    pub methods : Vec<Spanned<Rc<Function>>>,
+}
 
 use crate::ast::Expression::*;
 use crate::ast::Op::*;
 use crate::ast::Statement::*;
 
+// Discussion: Walking the tree.  Lots of passes need to go over the whole
+// tree but only care about a few kinds of nodes (say, a linter that looks
+// at variable names).  Rather than each of them having a match with every
+// kind of node in it, they can implement one of these traits and override
+// only the methods that they want.  Every method does the usual thing by
+// default: it calls the walk function, which goes on to the children.
+// An overridden method can call the walk function itself to keep going
+// down the tree (or not, to stop there).
+//
+// Visitor looks at the tree.  Fold takes the tree apart and builds a new
+// one, so it can change nodes or replace them with something else entirely.
+
+pub trait Visitor {
+    fn visit_expr(&mut self, expr : &Expr) {
+	walk_expr(self, expr)
+    }
+    fn visit_stmt(&mut self, stmt : &Stmt) {
+	walk_stmt(self, stmt)
+    }
+    fn visit_function(&mut self, function : &Function) {
+	walk_function(self, function)
+    }
+    fn visit_class(&mut self, class : &Class) {
+	walk_class(self, class)
+    }
+}
+
+pub fn walk_expr<V : Visitor + ?Sized>(visitor : &mut V, expr : &Expr) {
+    match &expr.node {
+	ENumber(_) | EString(_) | EBoolean(_) | ENil | EName(_) | EThis | ESuper(_) => { },
+	EBinary(_, left, right) | ELogical(_, left, right) => {
+	    visitor.visit_expr(left);
+	    visitor.visit_expr(right);
+	},
+	EUnary(_, value) | EGroup(value) => {
+	    visitor.visit_expr(value);
+	},
+	EInterpolate(parts, _) => {
+	    for (_, value) in parts {
+		visitor.visit_expr(value);
+	    }
+	},
+	ECall(callee, arguments) => {
+	    visitor.visit_expr(callee);
+	    for argument in arguments {
+		visitor.visit_expr(argument);
+	    }
+	},
+	EGet(object, _) => {
+	    visitor.visit_expr(object);
+	},
+	EAssign(target, value) => {
+	    visitor.visit_expr(target);
+	    visitor.visit_expr(value);
+	},
+    }
+}
+
+pub fn walk_stmt<V : Visitor + ?Sized>(visitor : &mut V, stmt : &Stmt) {
+    match &stmt.node {
+	SPrint(value) | SVar(_, value) | SExpr(value) | SReturn(Some(value)) => {
+	    visitor.visit_expr(value);
+	},
+	SIf(test, consequence, alternative) => {
+	    visitor.visit_expr(test);
+	    visitor.visit_stmt(consequence);
+	    if let Some(alternative) = alternative {
+		visitor.visit_stmt(alternative);
+	    }
+	},
+	SWhile(test, body, increment) => {
+	    visitor.visit_expr(test);
+	    visitor.visit_stmt(body);
+	    if let Some(increment) = increment {
+		visitor.visit_stmt(increment);
+	    }
+	},
+	SBlock(statements) => {
+	    for stmt in statements {
+		visitor.visit_stmt(stmt);
+	    }
+	},
+	SFunction(function) => {
+	    visitor.visit_function(function);
+	},
+	SClass(class) => {
+	    visitor.visit_class(class);
+	},
+	SReturn(None) | SBreak | SContinue => { },
+    }
+}
+
+pub fn walk_function<V : Visitor + ?Sized>(visitor : &mut V, function : &Function) {
+    for stmt in &function.body {
+	visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_class<V : Visitor + ?Sized>(visitor : &mut V, class : &Class) {
+    if let Some(superclass) = &class.superclass {
+	visitor.visit_expr(superclass);
+    }
+    for method in &class.methods {
+	visitor.visit_function(&method.node);
+    }
+}
+
+// Nodes keep their spans as they go through a fold (unless the fold
+// decides otherwise).
+pub trait Fold {
+    fn fold_expr(&mut self, expr : Expr) -> Expr {
+	fold_expr(self, expr)
+    }
+    fn fold_stmt(&mut self, stmt : Stmt) -> Stmt {
+	fold_stmt(self, stmt)
+    }
+    fn fold_function(&mut self, function : Function) -> Function {
+	fold_function(self, function)
+    }
+    fn fold_class(&mut self, class : Class) -> Class {
+	fold_class(self, class)
+    }
+}
+
+fn fold_boxed_expr<F : Fold + ?Sized>(folder : &mut F, expr : Expr) -> Box<Expr> {
+    Box::new(folder.fold_expr(expr))
+}
+
+fn fold_boxed_stmt<F : Fold + ?Sized>(folder : &mut F, stmt : Stmt) -> Box<Stmt> {
+    Box::new(folder.fold_stmt(stmt))
+}
+
+// A function can be shared (by the closures made from it), in which case
+// the fold gets a copy of it.
+fn fold_shared_function<F : Fold + ?Sized>(folder : &mut F, function : Rc<Function>) -> Rc<Function> {
+    Rc::new(folder.fold_function(Rc::unwrap_or_clone(function)))
+}
+
+pub fn fold_expr<F : Fold + ?Sized>(folder : &mut F, expr : Expr) -> Expr {
+    let node = match expr.node {
+	node @ (ENumber(_) | EString(_) | EBoolean(_) | ENil | EName(_) | EThis | ESuper(_)) => node,
+	EBinary(op, left, right) => EBinary(op, fold_boxed_expr(folder, *left), fold_boxed_expr(folder, *right)),
+	ELogical(op, left, right) => ELogical(op, fold_boxed_expr(folder, *left), fold_boxed_expr(folder, *right)),
+	EUnary(op, value) => EUnary(op, fold_boxed_expr(folder, *value)),
+	EGroup(value) => EGroup(fold_boxed_expr(folder, *value)),
+	EInterpolate(parts, tail) => {
+	    EInterpolate(parts.into_iter().map(|(text, value)| (text, folder.fold_expr(value))).collect(), tail)
+	},
+	ECall(callee, arguments) => {
+	    let callee = fold_boxed_expr(folder, *callee);
+	    ECall(callee, arguments.into_iter().map(|argument| folder.fold_expr(argument)).collect())
+	},
+	EGet(object, name) => EGet(fold_boxed_expr(folder, *object), name),
+	EAssign(target, value) => EAssign(fold_boxed_expr(folder, *target), fold_boxed_expr(folder, *value)),
+    };
+    Spanned::new(node, expr.span)
+}
+
+pub fn fold_stmt<F : Fold + ?Sized>(folder : &mut F, stmt : Stmt) -> Stmt {
+    let node = match stmt.node {
+	SPrint(value) => SPrint(folder.fold_expr(value)),
+	SVar(name, value) => SVar(name, folder.fold_expr(value)),
+	SExpr(value) => SExpr(folder.fold_expr(value)),
+	SIf(test, consequence, alternative) => {
+	    let test = folder.fold_expr(test);
+	    let consequence = fold_boxed_stmt(folder, *consequence);
+	    SIf(test, consequence, alternative.map(|alternative| fold_boxed_stmt(folder, *alternative)))
+	},
+	SWhile(test, body, increment) => {
+	    let test = folder.fold_expr(test);
+	    let body = fold_boxed_stmt(folder, *body);
+	    SWhile(test, body, increment.map(|increment| fold_boxed_stmt(folder, *increment)))
+	},
+	SBlock(statements) => SBlock(statements.into_iter().map(|stmt| folder.fold_stmt(stmt)).collect()),
+	SFunction(function) => SFunction(fold_shared_function(folder, function)),
+	SReturn(value) => SReturn(value.map(|value| folder.fold_expr(value))),
+	SClass(class) => SClass(folder.fold_class(class)),
+	node @ (SBreak | SContinue) => node,
+    };
+    Spanned::new(node, stmt.span)
+}
+
+pub fn fold_function<F : Fold + ?Sized>(folder : &mut F, function : Function) -> Function {
+    let body = function.body.into_iter().map(|stmt| folder.fold_stmt(stmt)).collect();
+    Function { body, ..function }
+}
+
+pub fn fold_class<F : Fold + ?Sized>(folder : &mut F, class : Class) -> Class {
+    let superclass = class.superclass.map(|superclass| folder.fold_expr(superclass));
+    let methods = class.methods.into_iter()
+	.map(|method| Spanned::new(fold_shared_function(folder, method.node), method.span))
+	.collect();
+    Class { name : class.name, superclass, methods }
+}
+
+// Discussion: Parentheses.  A tree doesn't need EGroup nodes to have the
+// right shape (one built by hand usually won't have any), so the formatter
+// can't count on them.  Instead, it puts parentheses around an operand
+// whenever it would otherwise parse back as part of something else.
+// Every kind of expression has a precedence (higher binds tighter).  These
+// are the same levels as in the parser:
+//
+//     1   assignment   =            groups to the right
+//     2   or
+//     3   and
+//     4   equality     == !=
+//     5   comparison   < <= > >=
+//     6   term         + -
+//     7   factor       * /
+//     8   unary        ! -
+//     9   call         f(...) .name
+//     10  primary      literals, names, this, super, ( ... )
+//
+// An operand with a lower precedence than the spot it's in needs
+// parentheses.  For the operators that group to the left, the right-hand
+// operand needs them at the same precedence too.  So, a - (b - c) keeps
+// its parentheses but (a - b) - c doesn't need any.
+//
+// Numbers are a special case.  Lox has no negative number literals (-1 is
+// unary minus applied to 1), but a tree built by hand or loaded from JSON
+// can have one, so it's printed as -1 with the precedence of a unary
+// operator.  There are no literals at all for infinity and NaN, so those
+// are printed as the division that makes them (1 / 0, -1 / 0 and 0 / 0).
+// Either way, the code gives back the same value when it runs.
+const ASSIGNMENT : u8 = 1;
+const UNARY : u8 = 8;
+const CALL : u8 = 9;
+const PRIMARY : u8 = 10;
+
+fn binary_precedence(op : Op) -> u8 {
+    match op {
+	OpOr => 2,
+	OpAnd => 3,
+	OpEq | OpNe => 4,
+	OpLt | OpLe | OpGt | OpGe => 5,
+	OpPlus | OpMinus => 6,
+	OpMult | OpDiv => 7,
+	OpNot => UNARY,
+    }
+}
+
+fn precedence(expr : &Expression) -> u8 {
+    match expr {
+	ENumber(value) if !value.is_finite() => binary_precedence(OpDiv),
+	ENumber(value) if value.is_sign_negative() => UNARY,
+	EAssign(..) => ASSIGNMENT,
+	EBinary(op, ..) | ELogical(op, ..) => binary_precedence(*op),
+	EUnary(..) => UNARY,
+	ECall(..) | EGet(..) => CALL,
+	_ => PRIMARY,
+    }
+}
+
+// Format an operand that has to bind at least as tightly as min
+fn format_operand(expr : &Expr, min : u8) -> String {
+    let text = format_expression(&expr.node);
+    if precedence(&expr.node) < min {
+	format!("({text})")
+    } else {
+	text
+    }
+}
+
 // Turn an expression into nicely formatted Lox code
 pub fn format_expression(expr : &Expression) -> String {
     match expr {
 	ENumber(value) => {
-	    value.to_string()
+	    if value.is_nan() {
+		String::from("0 / 0")
+	    } else if value.is_infinite() {
+		String::from(if *value > 0.0 { "1 / 0" } else { "-1 / 0" })
+	    } else {
+		value.to_string()
+	    }
 	},
 	EString(value) => {
-	    String::from(value)
+	    format!("\"{}\"", quote_text(value))
 	},
 	EBoolean(value) => {
 	    if *value { String::from("true") } else { String::from("false") }
@@ -86,42 +433,64 @@ pub fn format_expression(expr : &Expression) -> String {
 	EName(name) => {
 	    String::from(name)
 	}
-	EBinary(op, left, right) => {
-	    format!("{} {} {}", format_expression(left), op, format_expression(right))
+	EBinary(op, left, right) | ELogical(op, left, right) => {
+	    let level = binary_precedence(*op);
+	    format!("{} {} {}", format_operand(left, level), op, format_operand(right, level + 1))
 	},
 	EGroup(value) => {
-	    format!("({})", format_expression(value))
+	    format!("({})", format_expression(&value.node))
 	},
 	EUnary(op, value) => {
-	    format!("{}{}", op, format_expression(value))
-	}
-    }
-}
-
-pub fn format_statement(stmt : &Statement) -> String {
-    match stmt {
-	SPrint(value) => {
-	    format!("print {};\n", format_expression(value))
-	},
-	SExpr(value) => {
-	    format!("{};\n", format_expression(value))
+	    format!("{}{}", op, format_operand(value, UNARY))
 	},
-	SVar(name, value) => {
-	    format!("var {} = {};\n", name, format_expression(value))
+	EInterpolate(parts, tail) => {
+	    let mut result = String::from("\"");
+	    for (text, value) in parts {
+		result += &format!("{}${{{}}}", quote_text(text), format_expression(&value.node));
+	    }
+	    format!("{result}{}\"", quote_text(tail))
 	},
-	SIf(_test, _consequence, _alternative) => {
-	    todo!();
+	ECall(callee, arguments) => {
+	    let arguments : Vec<String> = arguments.iter().map(|arg| format_expression(&arg.node)).collect();
+	    format!("{}({})", format_operand(callee, CALL), arguments.join(", "))
 	},
-	SWhile(_test, _body) => {
-	    todo!();
+	EGet(object, name) => {
+	    format!("{}.{}", format_operand(object, CALL), name)
 	},
-	SAssignment(_location, _value) => {
-	    todo!();
+	EThis => {
+	    String::from("this")
 	},
-	SBlock(_statements) => {
-	    todo!();
+	ESuper(name) => {
+	    format!("super.{name}")
 	},
+	EAssign(target, value) => {
+	    format!("{} = {}", format_operand(target, CALL), format_operand(value, ASSIGNMENT))
+	}
+    }
+}
+
+// Turn a statement into nicely formatted Lox code (see format.rs)
+pub fn format_statement(stmt : &Statement) -> String {
+    crate::format::format_statement(stmt, Dialect::default())
+}
+
+// Put a string back in quotes, escaping anything that needs it.  The text
+// of an interpolation escapes "${" so it isn't taken as the start of one.
+pub fn quote_text(text : &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+	match ch {
+	    '\n' => result.push_str("\\n"),
+	    '\t' => result.push_str("\\t"),
+	    '\r' => result.push_str("\\r"),
+	    '"' => result.push_str("\\\""),
+	    '\\' => result.push_str("\\\\"),
+	    '$' if chars.peek() == Some(&'{') => result.push_str("\\$"),
+	    _ => result.push(ch),
+	}
     }
+    result
 }
 
 #[test]
@@ -130,22 +499,269 @@ pub fn test_formatting() {
 
     // 2 + 3
     let expr1 = EBinary(OpPlus,
-			Box::new(ENumber(2.0)),
-			Box::new(ENumber(3.0)));
+			Box::new(ENumber(2.0).into()),
+			Box::new(ENumber(3.0).into()));
     let fmt1 = format_expression(&expr1);
     assert_eq!(fmt1, "2 + 3");
-    
+
     // 2 + (3 * 4)
     let expr2 = EBinary(OpPlus,
-		       Box::new(ENumber(2.0)),
+		       Box::new(ENumber(2.0).into()),
 		       Box::new(EGroup(Box::new(EBinary(OpMult,
-							Box::new(ENumber(3.0)),
-							Box::new(ENumber(4.0)))))));
+							Box::new(ENumber(3.0).into()),
+							Box::new(ENumber(4.0).into())).into())).into()));
     let fmt2 = format_expression(&expr2);
     assert_eq!(fmt2, "2 + (3 * 4)");
 
     // print 2;
-    let stmt3 = SPrint(ENumber(2.0));
+    let stmt3 = SPrint(ENumber(2.0).into());
     let fmt3 = format_statement(&stmt3);
     assert_eq!(fmt3, "print 2;\n");
+
+    // "n = ${n + 1}!"
+    let expr4 = EInterpolate(vec![(String::from("n = "), EBinary(OpPlus,
+								   Box::new(EName(String::from("n")).into()),
+								   Box::new(ENumber(1.0).into())).into())],
+			     String::from("!"));
+    assert_eq!(format_expression(&expr4), "\"n = ${n + 1}!\"");
+
+    // fun add(a, b) { return a + b; }  add(1, 2);
+    let stmt5 = SFunction(Rc::new(Function {
+	name : String::from("add"),
+	params : vec![String::from("a"), String::from("b")],
+	body : vec![SReturn(Some(EBinary(OpPlus,
+					 Box::new(EName(String::from("a")).into()),
+					 Box::new(EName(String::from("b")).into())).into())).into()],
+    }));
+    assert_eq!(format_statement(&stmt5), "fun add(a, b) {\n    return a + b;\n}\n");
+    let expr6 = ECall(Box::new(EName(String::from("add")).into()),
+		      vec![ENumber(1.0).into(), ENumber(2.0).into()]);
+    assert_eq!(format_expression(&expr6), "add(1, 2)");
+
+    // class Point < Base { get() { return this.x; } }
+    let stmt7 = SClass(Class {
+	name : String::from("Point"),
+	superclass : Some(EName(String::from("Base")).into()),
+	methods : vec![Rc::new(Function {
+	    name : String::from("get"),
+	    params : vec![],
+	    body : vec![SReturn(Some(EGet(Box::new(EThis.into()), String::from("x")).into())).into()],
+	}).into()],
+    });
+    assert_eq!(format_statement(&stmt7), "class Point < Base {\n    get() {\n        return this.x;\n    }\n}\n");
+    assert_eq!(format_expression(&ECall(Box::new(ESuper(String::from("get")).into()), vec![])), "super.get()");
+
+    // if a { print 1; } else if b { } else print 2;
+    let block = |stmts : Statements| -> Box<Stmt> { Box::new(SBlock(stmts).into()) };
+    let stmt8 = SIf(EName(String::from("a")).into(),
+		    block(vec![SPrint(ENumber(1.0).into()).into()]),
+		    Some(Box::new(SIf(EName(String::from("b")).into(),
+				      block(vec![]),
+				      Some(Box::new(SPrint(ENumber(2.0).into()).into()))).into())));
+    assert_eq!(format_statement(&stmt8), "if a {\n    print 1;\n} else if b { } else print 2;\n");
+    let stmt9 = SIf(EName(String::from("a")).into(), block(vec![]), None);
+    assert_eq!(format_statement(&stmt9), "if a { }\n");
+    let expr10 = EAssign(Box::new(EName(String::from("a")).into()),
+			 Box::new(EAssign(Box::new(EGet(Box::new(EThis.into()), String::from("b")).into()),
+					  Box::new(ENumber(0.0).into())).into()));
+    assert_eq!(format_expression(&expr10), "a = this.b = 0");
+
+    // Parentheses go in where the tree needs them (even without an EGroup)
+    let number = |n : f64| -> Box<Expr> { Box::new(ENumber(n).into()) };
+    let name = |n : &str| -> Box<Expr> { Box::new(EName(String::from(n)).into()) };
+    let sum = || -> Box<Expr> { Box::new(EBinary(OpPlus, number(2.0), number(3.0)).into()) };
+    assert_eq!(format_expression(&EBinary(OpMult, sum(), number(4.0))), "(2 + 3) * 4");
+    assert_eq!(format_expression(&EBinary(OpPlus, number(1.0), sum())), "1 + (2 + 3)");
+    assert_eq!(format_expression(&EBinary(OpPlus, sum(), number(4.0))), "2 + 3 + 4");
+    assert_eq!(format_expression(&EUnary(OpMinus, sum())), "-(2 + 3)");
+    assert_eq!(format_expression(&EUnary(OpMinus, Box::new(EUnary(OpMinus, number(1.0)).into()))), "--1");
+    // Numbers that have no literal of their own
+    assert_eq!(format_expression(&ENumber(-2.5)), "-2.5");
+    assert_eq!(format_expression(&EGet(number(-2.0), String::from("x"))), "(-2).x");
+    assert_eq!(format_expression(&EBinary(OpMinus, number(1.0), number(-2.0))), "1 - -2");
+    assert_eq!(format_expression(&EBinary(OpMult, number(2.0), number(f64::INFINITY))), "2 * (1 / 0)");
+    assert_eq!(format_expression(&EBinary(OpPlus, number(f64::NEG_INFINITY), number(1.0))), "-1 / 0 + 1");
+    assert_eq!(format_expression(&EUnary(OpMinus, number(f64::NAN))), "-(0 / 0)");
+    assert_eq!(format_expression(&EGet(Box::new(ECall(name("f"), vec![]).into()), String::from("x"))), "f().x");
+    assert_eq!(format_expression(&ECall(Box::new(EUnary(OpNot, name("f")).into()), vec![])), "(!f)()");
+    assert_eq!(format_expression(&ELogical(OpAnd, Box::new(ELogical(OpOr, name("a"), name("b")).into()), name("c"))), "(a or b) and c");
+    assert_eq!(format_expression(&EBinary(OpEq, Box::new(EAssign(name("a"), number(1.0)).into()), Box::new(ENil.into()))), "(a = 1) == nil");
+}
+
+// A small random number generator (xorshift) for making up test trees
+#[cfg(test)]
+struct Rng(u64);
+
+#[cfg(test)]
+impl Rng {
+    fn next(&mut self) -> u64 {
+	self.0 ^= self.0 << 13;
+	self.0 ^= self.0 >> 7;
+	self.0 ^= self.0 << 17;
+	self.0
+    }
+    fn below(&mut self, n : usize) -> usize {
+	(self.next() % n as u64) as usize
+    }
+    fn pick<T : Copy>(&mut self, items : &[T]) -> T {
+	items[self.below(items.len())]
+    }
+}
+
+// A random expression that the parser could have produced (give or take
+// some parentheses)
+#[cfg(test)]
+fn random_expression(rng : &mut Rng, depth : usize) -> Expr {
+    let names = ["a", "b", "c"];
+    let texts = ["", "hi", "a\nb", "say \"q\"", "\\", "${x}", "$", "}", "{"];
+    if depth == 0 || rng.below(4) == 0 {
+	let node = match rng.below(8) {
+	    0 => ENumber(rng.pick(&[0.0, 1.0, 2.5, 0.125, 1e21, -1.0, -0.5, -1e21, -0.0])),
+	    1 => EString(String::from(rng.pick(&texts))),
+	    2 => EBoolean(rng.below(2) == 0),
+	    3 => ENil,
+	    4 => EThis,
+	    5 => ESuper(String::from(rng.pick(&names))),
+	    _ => EName(String::from(rng.pick(&names))),
+	};
+	return node.into();
+    }
+    let operand = |rng : &mut Rng| Box::new(random_expression(rng, depth - 1));
+    let node = match rng.below(8) {
+	0 => EBinary(rng.pick(&[OpPlus, OpMinus, OpMult, OpDiv, OpLt, OpLe, OpGt, OpGe, OpEq, OpNe]), operand(rng), operand(rng)),
+	1 => ELogical(rng.pick(&[OpAnd, OpOr]), operand(rng), operand(rng)),
+	2 => EUnary(rng.pick(&[OpMinus, OpNot]), operand(rng)),
+	3 => EGroup(operand(rng)),
+	4 => {
+	    let count = rng.below(3);
+	    ECall(operand(rng), (0..count).map(|_| *operand(rng)).collect())
+	},
+	5 => EGet(operand(rng), String::from(rng.pick(&names))),
+	6 => {
+	    let count = 1 + rng.below(2);
+	    let parts = (0..count).map(|_| (String::from(rng.pick(&texts)), *operand(rng))).collect();
+	    EInterpolate(parts, String::from(rng.pick(&texts)))
+	},
+	_ => {
+	    // Only a variable or a property can be assigned to
+	    let target = match rng.below(2) {
+		0 => EName(String::from(rng.pick(&names))),
+		_ => EGet(operand(rng), String::from(rng.pick(&names))),
+	    };
+	    EAssign(Box::new(target.into()), operand(rng))
+	},
+    };
+    node.into()
+}
+
+// Takes all of the EGroup nodes out of a tree and turns minus applied to
+// a number into a negative number (so -1 is the same however it was made)
+#[cfg(test)]
+struct Canonical;
+
+#[cfg(test)]
+impl Fold for Canonical {
+    fn fold_expr(&mut self, expr : Expr) -> Expr {
+	match expr.node {
+	    EGroup(value) => self.fold_expr(*value),
+	    _ => {
+		let expr = fold_expr(self, expr);
+		match &expr.node {
+		    EUnary(OpMinus, value) => match value.node {
+			ENumber(x) => Spanned::new(ENumber(-x), expr.span),
+			_ => expr,
+		    },
+		    _ => expr,
+		}
+	    },
+	}
+    }
+}
+
+#[test]
+fn test_format_round_trip() {
+    use crate::parse::{parse_tokens, Dialect};
+    use crate::tokenize::Scanner;
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..2000 {
+	let expr = random_expression(&mut rng, 4);
+	let text = format_expression(&expr.node);
+	// this and super are only allowed inside of a subclass
+	let src = format!("class A < B {{ m() {{ {text}; }} }}");
+	let ast = parse_tokens(Scanner::new(&src), Dialect::Rublox).unwrap_or_else(|errors| panic!("{text}: {}", errors[0]));
+	let SClass(class) = &ast[0].node else { panic!("expected a class") };
+	let SExpr(parsed) = &class.methods[0].node.body[0].node else { panic!("expected an expression") };
+	assert_eq!(Canonical.fold_expr(parsed.clone()), Canonical.fold_expr(expr), "{text}");
+    }
+}
+
+#[test]
+fn test_visitor() {
+    // Every variable that gets used, in order
+    struct Names(Vec<String>);
+    impl Visitor for Names {
+	fn visit_expr(&mut self, expr : &Expr) {
+	    if let EName(name) = &expr.node {
+		self.0.push(name.clone());
+	    }
+	    walk_expr(self, expr)
+	}
+	// Don't look inside of functions
+	fn visit_function(&mut self, _function : &Function) { }
+    }
+    let ast = crate::parse::parse("
+var a = b + c(d);
+fun f(x) { return x + e; }
+class C < D { m() { return g; } }
+if h { print \"${i}\"; } else { j.k = -l; }
+for (var m = n; o; p = p + 1) { }
+").unwrap();
+    let mut names = Names(Vec::new());
+    for stmt in &ast {
+	names.visit_stmt(stmt);
+    }
+    assert_eq!(names.0, vec!["b", "c", "d", "D", "h", "i", "j", "l", "n", "o", "p", "p"]);
+}
+
+#[test]
+fn test_fold() {
+    // Work out arithmetic on numbers ahead of time
+    struct Constants;
+    impl Fold for Constants {
+	fn fold_expr(&mut self, expr : Expr) -> Expr {
+	    let expr = fold_expr(self, expr);
+	    let value = match &expr.node {
+		EBinary(op, left, right) => match (op, &left.node, &right.node) {
+		    (OpPlus, ENumber(x), ENumber(y)) => x + y,
+		    (OpMult, ENumber(x), ENumber(y)) => x * y,
+		    _ => return expr,
+		},
+		EGroup(value) => match value.node {
+		    ENumber(x) => x,
+		    _ => return expr,
+		},
+		_ => return expr,
+	    };
+	    Spanned::new(ENumber(value), expr.span)
+	}
+    }
+    let ast = crate::parse::parse("print 2 + 3 * (4 + 1);\nfun f() { return x + 1 * 2; }\nclass C { m() { print 1 + 1; } }").unwrap();
+    let folded : Statements = ast.into_iter().map(|stmt| Constants.fold_stmt(stmt)).collect();
+    let formatted : Vec<String> = folded.iter().map(|stmt| format_statement(&stmt.node)).collect();
+    assert_eq!(formatted, vec!["print 17;\n",
+			       "fun f() {\n    return x + 2;\n}\n",
+			       "class C {\n    m() {\n        print 2;\n    }\n}\n"]);
+    // Spans are kept
+    assert_eq!(folded[0].span, Span::new(0, 22, 1, 1));
+    let SPrint(value) = &folded[0].node else { panic!("expected print") };
+    assert_eq!(value.span, Span::new(6, 21, 1, 7));
+}
+
+#[test]
+fn test_spans_ignored_by_eq() {
+    let a = Spanned::new(ENumber(2.0), Span::new(0, 1, 1, 1));
+    let b = Spanned::new(ENumber(2.0), Span::new(10, 11, 3, 5));
+    assert_eq!(a, b);
+    assert_eq!(a, ENumber(2.0));
+    assert_ne!(a, ENumber(3.0));
 }
\ No newline at end of file
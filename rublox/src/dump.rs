@@ -0,0 +1,528 @@
+// dump.rs
+//
+// Dump the AST as JSON or as S-expressions (to see what the parser made of
+// a program) and load it back in from JSON (so that other tools can make
+// programs for the interpreter).
+
+use std::rc::Rc;
+
+use crate::{Span, AST};
+use crate::ast::{quote_text, Class, Expr, Expression, Function, Op, Spanned, Statement, Stmt, Statements};
+use crate::ast::Expression::*;
+use crate::ast::Op::*;
+use crate::ast::Statement::*;
+use crate::json::{Json, JsonError};
+use crate::json::Json::*;
+
+// Discussion: The JSON schema.  A program is an array of statements.
+// Every node is an object with a "type" saying what kind of node it is, a
+// member for each of its parts, and its "span" as [start, end, line,
+// column].  A missing part (like an if with no else) is null.
+//
+//   number       value              print        expr
+//   string       value              var          name, value
+//   boolean      value              expression   expr
+//   nil                             if           test, then, else
+//   name         name               while        test, body, increment
+//   binary       op, left, right    block        statements
+//   logical      op, left, right    function     name, params, body
+//   unary        op, operand        return       value
+//   group        expr               class        name, superclass, methods
+//   interpolate  parts, tail        break
+//   call         callee, arguments  continue
+//   get          object, name
+//   this                            A method is like a function, but with
+//   super        name               "type": "method".  Each part of an
+//   assign       target, value      interpolation is {"text", "expr"}.
+//
+// Operators are written the way they are in Lox ("+", "and", "!").  JSON
+// numbers can't be infinite or NaN, so the value of a number node can also
+// be one of the strings "Infinity", "-Infinity" or "NaN".  When
+// loading, the span can be left out (the node gets an empty one).  The
+// loader checks that the tree is put together right and, like the parser,
+// that return, break and continue are only used where they make sense.
+// The rest of what the parser checks as it goes (like "this" outside of a
+// class) is left to the interpreter.
+
+pub fn to_json(ast : &AST) -> Json {
+    JArray(ast.iter().map(stmt_to_json).collect())
+}
+
+// Turn JSON text back into a program
+pub fn load_json(text : &str) -> Result<AST, JsonError> {
+    from_json(&Json::parse(text)?)
+}
+
+pub fn from_json(json : &Json) -> Result<AST, JsonError> {
+    statements_from_json(json, Place::default())
+}
+
+// Where a statement is, as far as return, break and continue go.  (The
+// same as the in_* flags in the parser.)
+#[derive(Clone, Copy, Default)]
+struct Place {
+    in_function : bool,
+    in_loop : bool,
+    in_initializer : bool,
+}
+
+// A node with the members in the order given (type first, span last)
+fn node(kind : &str, members : Vec<(&str, Json)>, span : Span) -> Json {
+    let mut object = vec![(String::from("type"), JString(String::from(kind)))];
+    object.extend(members.into_iter().map(|(name, value)| (String::from(name), value)));
+    object.push((String::from("span"), JArray(vec![JNumber(span.start as f64), JNumber(span.end as f64),
+                                                   JNumber(span.line as f64), JNumber(span.column as f64)])));
+    JObject(object)
+}
+
+fn text(value : &str) -> Json {
+    JString(String::from(value))
+}
+
+fn number(value : f64) -> Json {
+    if value.is_nan() {
+    text("NaN")
+    } else if value.is_infinite() {
+    text(if value > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+    JNumber(value)
+    }
+}
+
+fn expr_to_json(expr : &Expr) -> Json {
+    let boxed = |expr : &Expr| expr_to_json(expr);
+    let (kind, members) = match &expr.node {
+    ENumber(value) => ("number", vec![("value", number(*value))]),
+    EString(value) => ("string", vec![("value", text(value))]),
+    EBoolean(value) => ("boolean", vec![("value", JBool(*value))]),
+    ENil => ("nil", vec![]),
+    EName(name) => ("name", vec![("name", text(name))]),
+    EBinary(op, left, right) => ("binary", vec![("op", text(&op.to_string())), ("left", boxed(left)), ("right", boxed(right))]),
+    ELogical(op, left, right) => ("logical", vec![("op", text(&op.to_string())), ("left", boxed(left)), ("right", boxed(right))]),
+    EUnary(op, value) => ("unary", vec![("op", text(&op.to_string())), ("operand", boxed(value))]),
+    EGroup(value) => ("group", vec![("expr", boxed(value))]),
+    EInterpolate(parts, tail) => {
+        let parts = parts.iter()
+        .map(|(part, value)| JObject(vec![(String::from("text"), text(part)), (String::from("expr"), expr_to_json(value))]))
+        .collect();
+        ("interpolate", vec![("parts", JArray(parts)), ("tail", text(tail))])
+    },
+    ECall(callee, arguments) => ("call", vec![("callee", boxed(callee)), ("arguments", JArray(arguments.iter().map(expr_to_json).collect()))]),
+    EGet(object, name) => ("get", vec![("object", boxed(object)), ("name", text(name))]),
+    EThis => ("this", vec![]),
+    ESuper(name) => ("super", vec![("name", text(name))]),
+    EAssign(target, value) => ("assign", vec![("target", boxed(target)), ("value", boxed(value))]),
+    };
+    node(kind, members, expr.span)
+}
+
+fn stmt_to_json(stmt : &Stmt) -> Json {
+    let optional_stmt = |stmt : &Option<Box<Stmt>>| stmt.as_deref().map_or(JNull, stmt_to_json);
+    let (kind, members) = match &stmt.node {
+    SPrint(value) => ("print", vec![("expr", expr_to_json(value))]),
+    SVar(name, value) => ("var", vec![("name", text(name)), ("value", expr_to_json(value))]),
+    SExpr(value) => ("expression", vec![("expr", expr_to_json(value))]),
+    SIf(test, consequence, alternative) => {
+        ("if", vec![("test", expr_to_json(test)), ("then", stmt_to_json(consequence)), ("else", optional_stmt(alternative))])
+    },
+    SWhile(test, body, increment) => {
+        ("while", vec![("test", expr_to_json(test)), ("body", stmt_to_json(body)), ("increment", optional_stmt(increment))])
+    },
+    SBlock(statements) => ("block", vec![("statements", to_json(statements))]),
+    SFunction(function) => ("function", function_members(function)),
+    SReturn(value) => ("return", vec![("value", value.as_ref().map_or(JNull, expr_to_json))]),
+    SClass(class) => {
+        let methods = class.methods.iter().map(|method| node("method", function_members(&method.node), method.span)).collect();
+        ("class", vec![("name", text(&class.name)),
+                       ("superclass", class.superclass.as_ref().map_or(JNull, expr_to_json)),
+                       ("methods", JArray(methods))])
+    },
+    SBreak => ("break", vec![]),
+    SContinue => ("continue", vec![]),
+    };
+    node(kind, members, stmt.span)
+}
+
+fn function_members(function : &Function) -> Vec<(&'static str, Json)> {
+    vec![("name", text(&function.name)),
+         ("params", JArray(function.params.iter().map(|param| text(param)).collect())),
+         ("body", to_json(&function.body))]
+}
+
+// Loading.  Each of these helpers reads one member of a node.
+
+fn member<'j>(json : &'j Json, name : &str) -> Result<&'j Json, JsonError> {
+    json.get(name).ok_or_else(|| JsonError::new(&format!("Missing '{name}' in {}.", describe(json))))
+}
+
+// A member that can be null (or left out altogether)
+fn optional<'j>(json : &'j Json, name : &str) -> Option<&'j Json> {
+    json.get(name).filter(|value| **value != JNull)
+}
+
+fn string_member(json : &Json, name : &str) -> Result<String, JsonError> {
+    match member(json, name)? {
+    JString(value) => Ok(value.clone()),
+    _ => Err(JsonError::new(&format!("Expected a string for '{name}' in {}.", describe(json)))),
+    }
+}
+
+fn array_member<'j>(json : &'j Json, name : &str) -> Result<&'j [Json], JsonError> {
+    match member(json, name)? {
+    JArray(items) => Ok(items),
+    _ => Err(JsonError::new(&format!("Expected an array for '{name}' in {}.", describe(json)))),
+    }
+}
+
+fn expr_member(json : &Json, name : &str) -> Result<Box<Expr>, JsonError> {
+    Ok(Box::new(expr_from_json(member(json, name)?)?))
+}
+
+fn stmt_member(json : &Json, name : &str, place : Place) -> Result<Box<Stmt>, JsonError> {
+    Ok(Box::new(stmt_from_json(member(json, name)?, place)?))
+}
+
+fn op_member(json : &Json, name : &str, allowed : &[Op]) -> Result<Op, JsonError> {
+    let op = string_member(json, name)?;
+    allowed.iter().copied().find(|allowed| allowed.to_string() == op)
+    .ok_or_else(|| JsonError::new(&format!("Invalid operator '{op}' in {}.", describe(json))))
+}
+
+// What a node claims to be (for error messages)
+fn describe(json : &Json) -> String {
+    match json.get("type") {
+    Some(JString(kind)) => format!("{kind} node"),
+    _ => String::from("node"),
+    }
+}
+
+fn kind(json : &Json) -> Result<&str, JsonError> {
+    match json {
+    JObject(_) => match json.get("type") {
+        Some(JString(kind)) => Ok(kind),
+        _ => Err(JsonError::new("Missing 'type' in node.")),
+    },
+    _ => Err(JsonError::new("Expected an object for a node.")),
+    }
+}
+
+fn span(json : &Json) -> Result<Span, JsonError> {
+    let Some(value) = json.get("span") else {
+    return Ok(Span::default());
+    };
+    let numbers : Option<Vec<usize>> = match value {
+    JArray(items) if items.len() == 4 => items.iter().map(|item| match item {
+        JNumber(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+        _ => None,
+    }).collect(),
+    _ => None,
+    };
+    match numbers.as_deref() {
+    Some(&[start, end, line, column]) => Ok(Span::new(start, end, line, column)),
+    _ => Err(JsonError::new(&format!("Expected [start, end, line, column] for 'span' in {}.", describe(json)))),
+    }
+}
+
+fn statements_from_json(json : &Json, place : Place) -> Result<Statements, JsonError> {
+    match json {
+    JArray(items) => items.iter().map(|item| stmt_from_json(item, place)).collect(),
+    _ => Err(JsonError::new("Expected an array of statements.")),
+    }
+}
+
+fn expr_from_json(json : &Json) -> Result<Expr, JsonError> {
+    let binary = [OpPlus, OpMinus, OpMult, OpDiv, OpLt, OpLe, OpGt, OpGe, OpEq, OpNe];
+    let node = match kind(json)? {
+    "number" => match member(json, "value")? {
+        JNumber(value) => ENumber(*value),
+        JString(value) if value == "Infinity" => ENumber(f64::INFINITY),
+        JString(value) if value == "-Infinity" => ENumber(f64::NEG_INFINITY),
+        JString(value) if value == "NaN" => ENumber(f64::NAN),
+        _ => return Err(JsonError::new("Expected a number for 'value' in number node.")),
+    },
+    "string" => EString(string_member(json, "value")?),
+    "boolean" => match member(json, "value")? {
+        JBool(value) => EBoolean(*value),
+        _ => return Err(JsonError::new("Expected true or false for 'value' in boolean node.")),
+    },
+    "nil" => ENil,
+    "name" => EName(string_member(json, "name")?),
+    "binary" => EBinary(op_member(json, "op", &binary)?, expr_member(json, "left")?, expr_member(json, "right")?),
+    "logical" => ELogical(op_member(json, "op", &[OpAnd, OpOr])?, expr_member(json, "left")?, expr_member(json, "right")?),
+    "unary" => EUnary(op_member(json, "op", &[OpMinus, OpNot])?, expr_member(json, "operand")?),
+    "group" => EGroup(expr_member(json, "expr")?),
+    "interpolate" => {
+        let parts = array_member(json, "parts")?.iter()
+        .map(|part| Ok((string_member(part, "text")?, *expr_member(part, "expr")?)))
+        .collect::<Result<_, JsonError>>()?;
+        EInterpolate(parts, string_member(json, "tail")?)
+    },
+    "call" => {
+        let arguments = array_member(json, "arguments")?.iter().map(expr_from_json).collect::<Result<_, _>>()?;
+        ECall(expr_member(json, "callee")?, arguments)
+    },
+    "get" => EGet(expr_member(json, "object")?, string_member(json, "name")?),
+    "this" => EThis,
+    "super" => ESuper(string_member(json, "name")?),
+    "assign" => {
+        let target = expr_member(json, "target")?;
+        if !matches!(target.node, EName(_) | EGet(..)) {
+        return Err(JsonError::new("Invalid assignment target."));
+        }
+        EAssign(target, expr_member(json, "value")?)
+    },
+    other => return Err(JsonError::new(&format!("Unknown expression type '{other}'."))),
+    };
+    Ok(Spanned::new(node, span(json)?))
+}
+
+fn stmt_from_json(json : &Json, place : Place) -> Result<Stmt, JsonError> {
+    let optional_stmt = |name, place| optional(json, name).map(|value| stmt_from_json(value, place).map(Box::new)).transpose();
+    let in_loop = Place { in_loop : true, ..place };
+    let span = span(json)?;
+    let node = match kind(json)? {
+    "print" => SPrint(*expr_member(json, "expr")?),
+    "var" => SVar(string_member(json, "name")?, *expr_member(json, "value")?),
+    "expression" => SExpr(*expr_member(json, "expr")?),
+    "if" => SIf(*expr_member(json, "test")?, stmt_member(json, "then", place)?, optional_stmt("else", place)?),
+    "while" => SWhile(*expr_member(json, "test")?, stmt_member(json, "body", in_loop)?, optional_stmt("increment", in_loop)?),
+    "block" => SBlock(statements_from_json(member(json, "statements")?, place)?),
+    "function" => SFunction(Rc::new(function_from_json(json, false)?)),
+    "return" => {
+        let value = optional(json, "value").map(expr_from_json).transpose()?;
+        if !place.in_function {
+        return Err(JsonError::new("Can't return from top-level code."));
+        }
+        if value.is_some() && place.in_initializer {
+        return Err(JsonError::new("Can't return a value from an initializer."));
+        }
+        SReturn(value)
+    },
+    "class" => SClass(class_from_json(json)?),
+    "break" | "continue" if !place.in_loop => {
+        return Err(JsonError::new(&format!("Can't use '{}' outside of a loop.", kind(json)?)));
+    },
+    "break" => SBreak,
+    "continue" => SContinue,
+    other => return Err(JsonError::new(&format!("Unknown statement type '{other}'."))),
+    };
+    Ok(Spanned::new(node, span))
+}
+
+fn function_from_json(json : &Json, is_method : bool) -> Result<Function, JsonError> {
+    let params = array_member(json, "params")?.iter().map(|param| match param {
+    JString(param) => Ok(param.clone()),
+    _ => Err(JsonError::new(&format!("Expected a string for each of 'params' in {}.", describe(json)))),
+    }).collect::<Result<_, _>>()?;
+    let name = string_member(json, "name")?;
+    let place = Place { in_function : true, in_loop : false, in_initializer : is_method && name == "init" };
+    Ok(Function { body : statements_from_json(member(json, "body")?, place)?, name, params })
+}
+
+fn class_from_json(json : &Json) -> Result<Class, JsonError> {
+    let superclass = optional(json, "superclass").map(expr_from_json).transpose()?;
+    if superclass.as_ref().is_some_and(|superclass| !matches!(superclass.node, EName(_))) {
+    return Err(JsonError::new("Expected a name node for 'superclass' in class node."));
+    }
+    let methods = array_member(json, "methods")?.iter().map(|method| {
+    if kind(method)? != "method" {
+        return Err(JsonError::new("Expected a method node in 'methods'."));
+    }
+    Ok(Spanned::new(Rc::new(function_from_json(method, true)?), span(method)?))
+    }).collect::<Result<_, _>>()?;
+    Ok(Class { name : string_member(json, "name")?, superclass, methods })
+}
+
+// Discussion: S-expressions.  These are for people, not programs, so there's
+// no loader.  Every node is (kind parts...), with the operator as the kind
+// for operators: 2 + 3 * 4 is (+ 2 (* 3 4)).  Names, numbers, and the like
+// are written as themselves.  There's one statement per line at the top
+// level, but no other line breaks, so "diff" works on whole statements.
+
+pub fn to_sexp(ast : &AST) -> String {
+    ast.iter().map(|stmt| format!("{}\n", stmt_to_sexp(&stmt.node))).collect()
+}
+
+// Put a list together: (first rest...)
+fn list(first : &str, rest : Vec<String>) -> String {
+    if rest.is_empty() {
+    format!("({first})")
+    } else {
+    format!("({first} {})", rest.join(" "))
+    }
+}
+
+pub fn expr_to_sexp(expr : &Expression) -> String {
+    let sexp = |expr : &Expr| expr_to_sexp(&expr.node);
+    match expr {
+    ENumber(value) => value.to_string(),
+    EString(value) => format!("\"{}\"", quote_text(value)),
+    EBoolean(value) => value.to_string(),
+    ENil => String::from("nil"),
+    EName(name) => name.clone(),
+    EBinary(op, left, right) | ELogical(op, left, right) => list(&op.to_string(), vec![sexp(left), sexp(right)]),
+    EUnary(op, value) => list(&op.to_string(), vec![sexp(value)]),
+    EGroup(value) => list("group", vec![sexp(value)]),
+    EInterpolate(parts, tail) => {
+        let mut items = Vec::new();
+        for (part, value) in parts {
+        items.push(format!("\"{}\"", quote_text(part)));
+        items.push(sexp(value));
+        }
+        items.push(format!("\"{}\"", quote_text(tail)));
+        list("interpolate", items)
+    },
+    ECall(callee, arguments) => list("call", std::iter::once(callee.as_ref()).chain(arguments).map(sexp).collect()),
+    EGet(object, name) => list(".", vec![sexp(object), name.clone()]),
+    EThis => String::from("this"),
+    ESuper(name) => list("super", vec![name.clone()]),
+    EAssign(target, value) => list("=", vec![sexp(target), sexp(value)]),
+    }
+}
+
+pub fn stmt_to_sexp(stmt : &Statement) -> String {
+    let sexp = |stmt : &Stmt| stmt_to_sexp(&stmt.node);
+    match stmt {
+    SPrint(value) => list("print", vec![expr_to_sexp(&value.node)]),
+    SVar(name, value) => list("var", vec![name.clone(), expr_to_sexp(&value.node)]),
+    SExpr(value) => list("expr", vec![expr_to_sexp(&value.node)]),
+    SIf(test, consequence, alternative) => {
+        let mut items = vec![expr_to_sexp(&test.node), sexp(consequence)];
+        items.extend(alternative.as_deref().map(sexp));
+        list("if", items)
+    },
+    SWhile(test, body, increment) => {
+        let mut items = vec![expr_to_sexp(&test.node), sexp(body)];
+        items.extend(increment.as_deref().map(sexp));
+        list("while", items)
+    },
+    SBlock(statements) => list("block", statements.iter().map(sexp).collect()),
+    SFunction(function) => function_to_sexp(function),
+    SReturn(value) => list("return", value.iter().map(|value| expr_to_sexp(&value.node)).collect()),
+    SClass(class) => {
+        let mut items = vec![class.name.clone()];
+        items.extend(class.superclass.as_ref().map(|superclass| list("<", vec![expr_to_sexp(&superclass.node)])));
+        items.extend(class.methods.iter().map(|method| function_to_sexp(&method.node)));
+        list("class", items)
+    },
+    SBreak => list("break", vec![]),
+    SContinue => list("continue", vec![]),
+    }
+}
+
+fn function_to_sexp(function : &Function) -> String {
+    let mut items = vec![function.name.clone(), format!("({})", function.params.join(" "))];
+    items.extend(function.body.iter().map(|stmt| stmt_to_sexp(&stmt.node)));
+    list("fun", items)
+}
+
+#[cfg(test)]
+use crate::parse::parse;
+
+#[test]
+fn test_sexp() {
+    let sexp = |src : &str| to_sexp(&parse(src).unwrap());
+    assert_eq!(sexp("print 2 + 3 * 4;"), "(print (+ 2 (* 3 4)))\n");
+    assert_eq!(sexp("var x; x = -(1) or !\"a\\n\";\nf(x).y(\"${x}!\");"),
+               "(var x nil)\n(expr (= x (or (- (group 1)) (! \"a\\n\"))))\n(expr (call (. (call f x) y) (interpolate \"\" x \"!\")))\n");
+    assert_eq!(sexp("if a { } else if b print 1;\nfor (var i = 0; i < 3; i = i + 1) { break; continue; }"),
+               "(if a (block) (if b (print 1)))\n(block (var i 0) (while (< i 3) (block (break) (continue)) (expr (= i (+ i 1)))))\n");
+    assert_eq!(sexp("fun f(a, b) { return; }\nclass A < B { m() { return super.m(this); } }\nclass C { }"),
+               "(fun f (a b) (return))\n(class A (< B) (fun m () (return (call (super m) this))))\n(class C)\n");
+}
+
+#[test]
+fn test_json() {
+    let ast = parse("print 2 + 3;").unwrap();
+    assert_eq!(to_json(&ast).to_string(), r#"[
+  {
+    "type": "print",
+    "expr": {
+      "type": "binary",
+      "op": "+",
+      "left": {
+        "type": "number",
+        "value": 2,
+        "span": [6, 7, 1, 7]
+      },
+      "right": {
+        "type": "number",
+        "value": 3,
+        "span": [10, 11, 1, 11]
+      },
+      "span": [6, 11, 1, 7]
+    },
+    "span": [0, 12, 1, 1]
+  }
+]"#);
+    // Everything makes it there and back again (spans included)
+    let src = "
+var s = \"a ${b.c} d\";
+fun f(x, y) { if x { return y; } else return; }
+class A < B {
+  init() { this.x = super.init(1, (2)); }
+}
+for (var i = 0; i < 3; i = i + 1) { if i == 1 continue; else break; }
+while !true and nil { print -1; }
+";
+    let ast = parse(src).unwrap();
+    let text = to_json(&ast).to_string();
+    let loaded = load_json(&text).unwrap();
+    assert_eq!(loaded, ast);
+    assert_eq!(to_json(&loaded).to_string(), text);
+    assert_eq!(to_sexp(&loaded), to_sexp(&ast));
+
+    // Numbers that JSON can't write
+    let numbers = [-1.5, f64::INFINITY, f64::NEG_INFINITY, f64::NAN];
+    let ast : AST = numbers.iter().map(|n| SPrint(ENumber(*n).into()).into()).collect();
+    let text = to_json(&ast).to_string();
+    assert!(text.contains("\"value\": \"-Infinity\""));
+    let loaded = load_json(&text).unwrap();
+    assert_eq!(loaded[..3], ast[..3]);
+    assert!(matches!(loaded[3].node, SPrint(Spanned { node : ENumber(n), .. }) if n.is_nan()));
+}
+
+#[test]
+fn test_load_json() {
+    // Spans can be left out
+    let ast = load_json(r#"[{"type": "print", "expr": {"type": "unary", "op": "-", "operand": {"type": "number", "value": 1}}}]"#).unwrap();
+    assert_eq!(ast, parse("print -1;").unwrap());
+    assert_eq!(ast[0].span, Span::default());
+    assert_eq!(load_json("[]"), Ok(vec![]));
+
+    let message = |text : &str| load_json(text).unwrap_err().to_string();
+    assert_eq!(message("{}"), "Error: Expected an array of statements.");
+    assert_eq!(message("[1]"), "Error: Expected an object for a node.");
+    assert_eq!(message(r#"[{"expr": null}]"#), "Error: Missing 'type' in node.");
+    assert_eq!(message(r#"[{"type": "goto"}]"#), "Error: Unknown statement type 'goto'.");
+    assert_eq!(message(r#"[{"type": "print"}]"#), "Error: Missing 'expr' in print node.");
+    assert_eq!(message(r#"[{"type": "print", "expr": {"type": "print"}}]"#), "Error: Unknown expression type 'print'.");
+    assert_eq!(message(r#"[{"type": "expression", "expr": {"type": "unary", "op": "+", "operand": {"type": "nil"}}}]"#),
+               "Error: Invalid operator '+' in unary node.");
+    assert_eq!(message(r#"[{"type": "expression", "expr": {"type": "assign", "target": {"type": "nil"}, "value": {"type": "nil"}}}]"#),
+               "Error: Invalid assignment target.");
+    assert_eq!(message(r#"[{"type": "var", "name": 1, "value": {"type": "nil"}}]"#), "Error: Expected a string for 'name' in var node.");
+    assert_eq!(message(r#"[{"type": "print", "expr": {"type": "number", "value": "inf"}}]"#),
+               "Error: Expected a number for 'value' in number node.");
+    assert_eq!(message(r#"[{"type": "break", "span": [1, 2]}]"#), "Error: Expected [start, end, line, column] for 'span' in break node.");
+    assert_eq!(message(r#"[{"type": "class", "name": "A", "methods": [{"type": "function"}]}]"#),
+               "Error: Expected a method node in 'methods'.");
+    assert_eq!(message("[{"), "Error: Expected a member name. (line 1, column 3)");
+
+    // Same rules as the parser for where return, break and continue go
+    let body = |stmt : &str| format!(r#"{{"type": "block", "statements": [{stmt}]}}"#);
+    let function = |name : &str, stmt : &str| format!(r#"{{"type": "function", "name": "{name}", "params": [], "body": [{stmt}]}}"#);
+    let method = |name : &str, stmt : &str| function(name, stmt).replace("function", "method");
+    let class = |stmt : &str| format!(r#"[{{"type": "class", "name": "A", "methods": [{}]}}]"#, method("init", stmt));
+    let while_true = |stmt : &str| format!(r#"{{"type": "while", "test": {{"type": "boolean", "value": true}}, "body": {stmt}}}"#);
+    let ret = r#"{"type": "return", "value": {"type": "nil"}}"#;
+    let brk = r#"{"type": "break"}"#;
+    assert_eq!(message(&format!("[{}]", body(ret))), "Error: Can't return from top-level code.");
+    assert_eq!(message(r#"[{"type": "if", "test": {"type": "nil"}, "then": {"type": "continue"}}]"#),
+               "Error: Can't use 'continue' outside of a loop.");
+    assert_eq!(message(&format!("[{}]", while_true(&function("f", brk)))), "Error: Can't use 'break' outside of a loop.");
+    assert_eq!(message(&class(ret)), "Error: Can't return a value from an initializer.");
+    assert!(load_json(&format!("[{}]", function("f", &while_true(&body(brk))))).is_ok());
+    assert!(load_json(&format!("[{}]", function("init", ret))).is_ok());
+    assert!(load_json(&class(r#"{"type": "return"}"#)).is_ok());
+}
@@ -9,6 +9,7 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::interp::LoxValue;
+#[cfg(test)]
 use crate::interp::LoxValue::*;
 
 /*
@@ -69,14 +70,16 @@ impl Environment {
     }
     }
     // Set the value of an existing variable, deleting its old value.
-    pub fn set(&self, name: &str, value: LoxValue) {
+    // It's an error to set a variable that was never defined.
+    pub fn set(&self, name: &str, value: LoxValue) -> Result<(), String> {
     let mut vals = self.values.borrow_mut();
     if vals.contains_key(name) {
         vals.insert(name.to_string(), value.clone());
+        Ok(())
     } else if let Some(parent) = &self.parent {
-        parent.set(name, value);
+        parent.set(name, value)
     } else {
-        panic!("setting non-existent variable");   // FIXME
+        Err(format!("Undefined variable '{name}'."))
     }
     }
 }
@@ -86,6 +89,7 @@ fn test_environment() {
     let env = Environment::new();
     env.define("x", LNumber(4.0));
     assert_eq!(env.lookup("x"), Some(LNumber(4.0)));
-    env.set("x", LNumber(10.0));
+    assert_eq!(env.set("x", LNumber(10.0)), Ok(()));
     assert_eq!(env.lookup("x"), Some(LNumber(10.0)));
+    assert!(env.set("y", LNumber(1.0)).is_err());
 }
\ No newline at end of file
@@ -0,0 +1,396 @@
+// format.rs
+//
+// Format Lox source code (the "rublox fmt" command)
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::Span;
+use crate::TokenType::*;
+use crate::ast::{format_expression, Class, Expr, Function, Spanned, Statement, Stmt};
+use crate::ast::Expression::*;
+use crate::ast::Statement::*;
+use crate::parse::{parse_tokens, source_dialect, Dialect, SyntaxError};
+use crate::tokenize::{tokenize_lossless, Scanner};
+
+// Discussion: The AST doesn't have any comments in it, so they have to come
+// from somewhere else.  The lossless tokens carry every comment as trivia
+// (along with its span).  Each time the formatter is about to print a
+// statement, it first prints all of the comments that came before that
+// statement in the source.  A comment on the same line as the end of a
+// statement stays there.  A comment in an odd spot (say, the middle of an
+// expression) can't stay exactly where it was, so it ends up right after
+// the statement that it was in.
+//
+// All of the whitespace is decided by the formatter, except that a blank
+// line between two statements is kept (several blank lines become one).
+// Nothing in the output depends on how the input was laid out beyond that,
+// so formatting already formatted code gives back exactly the same thing.
+//
+// Expressions are printed by format_expression() in ast.rs.  Only
+// statements have any layout to them.
+
+const INDENT : &str = "    ";
+
+// Format a whole program.  The dialect is used unless the file has a pragma
+// that says otherwise.
+pub fn format_source(src : &str, dialect : Dialect) -> Result<String, Vec<SyntaxError>> {
+    let dialect = source_dialect(src, dialect)?;
+    let ast = parse_tokens(Scanner::new(src), dialect)?;
+    let tokens = tokenize_lossless(&src.to_string()).map_err(|errors| errors.into_iter().map(SyntaxError::Lex).collect::<Vec<_>>())?;
+    let comments = tokens.iter()
+    .flat_map(|tok| tok.leading().iter().chain(tok.trailing()))
+    .filter(|trivia| trivia.toktype() == COMMENT)
+    .map(|trivia| (trivia.span(), trivia.lexeme().to_string()))
+    .collect();
+    let mut formatter = Formatter { source : src, comments, ..Formatter::new(dialect) };
+    for stmt in ast.iter() {
+    formatter.line(stmt, Formatter::statement);
+    }
+    // Comments at the very end
+    formatter.comments_before(usize::MAX);
+    Ok(formatter.out)
+}
+
+// Format a single statement that didn't come from any source
+pub fn format_statement(stmt : &Statement, dialect : Dialect) -> String {
+    let mut formatter = Formatter::new(dialect);
+    formatter.statement(stmt, Span::default());
+    formatter.out.push('\n');
+    formatter.out
+}
+
+struct Formatter<'a> {
+    out : String,                           // Formatted code
+    dialect : Dialect,
+    depth : usize,                          // Current level of indentation
+    source : &'a str,                       // Where the tree came from (if anywhere)
+    comments : VecDeque<(Span, String)>,    // Comments not printed yet (in order)
+    last_end : usize,                       // End (in the source) of the last thing printed
+    block_start : bool,                     // Nothing printed yet in the current block?
+}
+
+impl<'a> Formatter<'a> {
+    fn new(dialect : Dialect) -> Formatter<'a> {
+    Formatter { out : String::new(), dialect, depth : 0, source : "", comments : VecDeque::new(),
+                last_end : 0, block_start : true }
+    }
+
+    fn indent(&mut self) {
+    for _ in 0..self.depth {
+        self.out.push_str(INDENT);
+    }
+    }
+
+    // Start a new line for something that begins at start in the source.
+    // If there was a blank line in front of it, there's one in the output
+    // too (except first thing in a block).
+    fn new_line(&mut self, start : usize) {
+    let blank = self.source.get(self.last_end..start).is_some_and(|gap| gap.matches('\n').count() > 1);
+    if blank && !self.block_start {
+        self.out.push('\n');
+    }
+    self.block_start = false;
+    self.indent();
+    }
+
+    // Print the comments that start before offset, each on a line of its own
+    fn comments_before(&mut self, offset : usize) {
+    while self.comments.front().is_some_and(|(span, _)| span.start < offset) {
+        let (span, text) = self.comments.pop_front().unwrap();
+        self.new_line(span.start);
+        self.out.push_str(&text);
+        self.out.push('\n');
+        self.last_end = span.end;
+    }
+    }
+
+    // Print the comments that go at the end of the line for something that
+    // ended at end in the source.  That's any comment inside of it that
+    // hasn't been printed yet, plus one that follows it on the same line.
+    fn trailing_comments(&mut self, end : usize) {
+    let mut first = true;
+    while let Some((span, _)) = self.comments.front() {
+        let inside = span.start < end;
+        let same_line = self.source.get(self.last_end..span.start).is_some_and(|gap| gap.trim().is_empty() && !gap.contains('\n'));
+        if !(inside || same_line) {
+        break;
+        }
+        let (span, text) = self.comments.pop_front().unwrap();
+        if first {
+        self.out.push(' ');
+        } else {
+        // A // comment runs to the end of the line, so nothing can follow it
+        self.out.push('\n');
+        self.indent();
+        }
+        self.out.push_str(&text);
+        self.last_end = self.last_end.max(span.end);
+        first = false;
+    }
+    }
+
+    // Print a statement (or method) on line(s) of its own, along with the
+    // comments around it
+    fn line<T>(&mut self, node : &Spanned<T>, print : fn(&mut Self, &T, Span)) {
+    self.comments_before(node.span.start);
+    self.new_line(node.span.start);
+    print(self, &node.node, node.span);
+    self.last_end = node.span.end;
+    self.trailing_comments(node.span.end);
+    self.out.push('\n');
+    }
+
+    // Print { ... } with everything inside indented one more level.  end is
+    // where the closing brace ends in the source.
+    fn braces<T>(&mut self, nodes : &[Spanned<T>], end : usize, print : fn(&mut Self, &T, Span)) {
+    if nodes.is_empty() && self.comments.front().is_none_or(|(span, _)| span.start >= end) {
+        self.out.push_str("{ }");
+        return;
+    }
+    self.out.push_str("{\n");
+    self.depth += 1;
+    self.block_start = true;
+    for node in nodes {
+        self.line(node, print);
+    }
+    self.comments_before(end);
+    self.depth -= 1;
+    self.indent();
+    self.out.push('}');
+    }
+
+    // The test of an if or a while
+    fn condition(&mut self, test : &Expr) {
+    let test = format_expression(&test.node);
+    match self.dialect {
+        Dialect::Rublox => self.out.push_str(&test),
+        Dialect::Standard => self.out.push_str(&format!("({test})")),
+    }
+    }
+
+    // The body of an if, while, or for.  It goes on the same line (it's
+    // usually a block anyway).
+    fn body(&mut self, body : &Stmt) {
+    self.out.push(' ');
+    self.statement(&body.node, body.span);
+    }
+
+    // Discussion: A for-loop was turned into a while-loop by the parser, but
+    // it's put back the way it was.  A while-loop with an increment can only
+    // have come from a for-loop.  The initializer is trickier.  It ends up
+    // in a block around the loop, and that block starts at the "for" in the
+    // source.  A block that was written out starts with '{' instead.  (A
+    // tree built by hand has no source, so the block gets printed as-is.
+    // That's fine since it means the same thing.)
+    fn for_loop(&mut self, init : Option<&Stmt>, test : &Expr, body : &Stmt, increment : Option<&Stmt>) {
+    self.out.push_str("for (");
+    match init {
+        Some(init) => self.statement(&init.node, init.span),
+        None => self.out.push(';'),
+    }
+    self.out.push_str(&format!(" {};", format_expression(&test.node)));
+    if let Some(Spanned { node : SExpr(increment), .. }) = increment {
+        self.out.push_str(&format!(" {}", format_expression(&increment.node)));
+    }
+    self.out.push(')');
+    self.body(body);
+    }
+
+    fn statement(&mut self, stmt : &Statement, span : Span) {
+    match stmt {
+        SPrint(value) => {
+        self.out.push_str(&format!("print {};", format_expression(&value.node)));
+        },
+        SExpr(value) => {
+        self.out.push_str(&format!("{};", format_expression(&value.node)));
+        },
+        SVar(name, value) => {
+        // "var x;" and "var x = nil;" are the same thing
+        match value.node {
+            ENil => self.out.push_str(&format!("var {name};")),
+            _ => self.out.push_str(&format!("var {} = {};", name, format_expression(&value.node))),
+        }
+        },
+        SIf(test, consequence, alternative) => {
+        self.out.push_str("if ");
+        self.condition(test);
+        self.body(consequence);
+        if let Some(alternative) = alternative {
+            self.out.push_str(" else ");
+            self.statement(&alternative.node, alternative.span);
+        }
+        },
+        SWhile(test, body, None) => {
+        self.out.push_str("while ");
+        self.condition(test);
+        self.body(body);
+        },
+        SWhile(test, body, Some(increment)) => {
+        self.for_loop(None, test, body, Some(increment));
+        },
+        SBlock(statements) => {
+        match &statements[..] {
+            [init @ Spanned { node : SVar(..) | SExpr(..), .. }, Spanned { node : SWhile(test, body, increment), .. }]
+            if self.source.get(span.start..).is_some_and(|rest| rest.starts_with("for")) => {
+            self.for_loop(Some(init), test, body, increment.as_deref());
+            },
+            _ => self.braces(statements, span.end, Formatter::statement),
+        }
+        },
+        SFunction(function) => {
+        self.out.push_str("fun ");
+        self.function(function, span);
+        },
+        SClass(class) => {
+        self.class(class, span);
+        },
+        SReturn(Some(value)) => {
+        self.out.push_str(&format!("return {};", format_expression(&value.node)));
+        },
+        SReturn(None) => {
+        self.out.push_str("return;");
+        },
+        SBreak => {
+        self.out.push_str("break;");
+        },
+        SContinue => {
+        self.out.push_str("continue;");
+        },
+    }
+    }
+
+    // A function (or method) without the "fun" in front
+    fn function(&mut self, function : &Rc<Function>, span : Span) {
+    self.out.push_str(&format!("{}({}) ", function.name, function.params.join(", ")));
+    self.braces(&function.body, span.end, Formatter::statement);
+    }
+
+    fn class(&mut self, class : &Class, span : Span) {
+    self.out.push_str(&format!("class {} ", class.name));
+    if let Some(superclass) = &class.superclass {
+        self.out.push_str(&format!("< {} ", format_expression(&superclass.node)));
+    }
+    self.braces(&class.methods, span.end, Formatter::function);
+    }
+}
+
+#[test]
+fn test_format_source() {
+    let format = |src : &str| format_source(src, Dialect::Rublox).expect("syntax error");
+    assert_eq!(format("print   1+2 ;var x=\"a\\tb\";"), "print 1 + 2;\nvar x = \"a\\tb\";\n");
+    let src = "
+fun fib(n){if n<2 {return n;} return fib(n-1)+fib(n-2);}
+class A<B{init(x){this.x=x;}get(){return super.get();}}
+for(var i=0;i<3;i=i+1)print i;
+while true { if x { break; } else if y { continue; } else { } }
+var z;
+{ }
+";
+    assert_eq!(format(src), "\
+fun fib(n) {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+class A < B {
+    init(x) {
+        this.x = x;
+    }
+    get() {
+        return super.get();
+    }
+}
+for (var i = 0; i < 3; i = i + 1) print i;
+while true {
+    if x {
+        break;
+    } else if y {
+        continue;
+    } else { }
+}
+var z;
+{ }
+");
+    // Loops that only look like for-loops stay the way they were
+    assert_eq!(format("{ var i = 0; while i < 3 { i = i + 1; } }\nfor (;;) print 1;\nfor (x = 1; ; ) { }"),
+               "{\n    var i = 0;\n    while i < 3 {\n        i = i + 1;\n    }\n}\nwhile true print 1;\nfor (x = 1; true;) { }\n");
+    // Formatted code is already formatted
+    let once = format(src);
+    assert_eq!(format(&once), once);
+}
+
+#[test]
+fn test_format_comments() {
+    let format = |src : &str| format_source(src, Dialect::Rublox).expect("syntax error");
+    let src = "\
+// Leading comment
+
+print 1;   // Trailing comment
+/* Before */ print 2;
+
+
+// Before a function
+fun f() {
+  // Inside
+  return 1 +  // In an expression
+    2;
+  // At the end
+}
+class C {
+  // Before a method
+  m() { }
+  /* Last */
+}
+if true { } // After a block
+print \"//not a comment\";
+// At the end of the file";
+    let expected = "\
+// Leading comment
+
+print 1; // Trailing comment
+/* Before */
+print 2;
+
+// Before a function
+fun f() {
+    // Inside
+    return 1 + 2; // In an expression
+    // At the end
+}
+class C {
+    // Before a method
+    m() { }
+    /* Last */
+}
+if true { } // After a block
+print \"//not a comment\";
+// At the end of the file
+";
+    assert_eq!(format(src), expected);
+    assert_eq!(format(expected), expected);
+    assert_eq!(format("{\n  // Only a comment\n}"), "{\n    // Only a comment\n}\n");
+}
+
+#[test]
+fn test_format_dialects() {
+    let src = "if (x > 3) print x; else print -x;\nwhile (x > 0) x = x - 1;";
+    let expected = "if (x > 3) print x; else print -x;\nwhile (x > 0) x = x - 1;\n";
+    assert_eq!(format_source(src, Dialect::Standard), Ok(String::from(expected)));
+    // The pragma is a comment, so it stays put
+    let src = format!("// lox-dialect: standard\n{src}");
+    assert_eq!(format_source(&src, Dialect::Rublox), Ok(format!("// lox-dialect: standard\n{expected}")));
+    assert_eq!(format_source("if x > 3 { print x; }", Dialect::Rublox), Ok(String::from("if x > 3 {\n    print x;\n}\n")));
+    let errors = format_source("print 1 +;", Dialect::Rublox).unwrap_err();
+    assert_eq!(errors[0].to_string(), "[line 1] Error at ';': Expect expression.");
+}
+
+#[test]
+fn test_format_strings() {
+    let format = |src : &str| format_source(src, Dialect::Rublox).expect("syntax error");
+    let src = "print \"say \\\"hi\\\"\\n\\\\\" + \"\\${not} $5 ${1 + 2}\\u{41}\";\n";
+    let once = format(src);
+    assert_eq!(once, "print \"say \\\"hi\\\"\\n\\\\\" + \"\\${not} $5 ${1 + 2}A\";\n");
+    assert_eq!(format(&once), once);
+}
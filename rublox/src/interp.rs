@@ -2,19 +2,25 @@
 //
 // Interpret Lox code
 
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
-use crate::AST;
+use crate::{Span, AST};
 use crate::ast::Expression::*;
 use crate::ast::Statement::*;
-use crate::ast::{Expression, Statement, Statements};
+use crate::ast::{Expr, Function, Op, Spanned, Stmt, Statements};
 use crate::ast::Op::*;
-use crate::parse::parse_expression_string;
 use crate::environ::Environment;
 
-pub fn interpret(ast : &AST) {
+pub fn interpret(ast : &AST) -> Result<(), RuntimeError> {
     println!("========= Interpreting Lox");
-    interpret_statements(ast, &Environment::new());
+    let environ = Environment::new();
+    for stmt in ast.iter() {
+    check_flow(interpret_statement(stmt, &environ)?, stmt.span)?;
+    }
+    Ok(())
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -22,67 +28,325 @@ pub enum LoxValue {
     LNumber(f64),       // Runtime representation of Lox values.
     LString(String),
     LBoolean(bool),
-    LNil
+    LNil,
+    LFunction(Rc<LoxFunction>),
+    LClass(Rc<LoxClass>),
+    LInstance(Rc<LoxInstance>),
+}
+
+// A function value (what a "fun" statement makes when it runs).  It's the
+// function paired with the environment it was defined in (the closure), so
+// the function can still get at the variables around it after the block
+// that made it has finished.  See the discussion in environ.rs.
+//
+// Note: A function defined in an environment usually gets stored in that
+// same environment, so the Rc's form a cycle and never get freed.  That's
+// a leak, but a small one.  Fixing it would take a real garbage collector.
+pub struct LoxFunction {
+    pub declaration : Rc<Function>,
+    pub closure : Rc<Environment>,
+    pub is_initializer : bool,      // The init() method of a class
+}
+
+impl LoxFunction {
+    // Turn a method into a bound method for a particular instance.  The
+    // method gets a closure of its own where "this" is the instance.
+    fn bind(&self, instance : &Rc<LoxInstance>) -> LoxFunction {
+    let closure = Environment::new_scope(&self.closure);
+    closure.define("this", LInstance(instance.clone()));
+    LoxFunction { declaration : self.declaration.clone(), closure, is_initializer : self.is_initializer }
+    }
+}
+
+// Two function values are only equal if they're the very same one
+impl PartialEq for LoxFunction {
+    fn eq(&self, other : &LoxFunction) -> bool {
+    std::ptr::eq(self, other)
+    }
+}
+
+// Printing the whole environment would be way too much (and it can contain
+// the function itself)
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<fn {}>", self.declaration.name)
+    }
+}
+
+// A class value.  Calling it makes a new instance.
+pub struct LoxClass {
+    pub name : String,
+    pub superclass : Option<Rc<LoxClass>>,
+    pub methods : HashMap<String, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    // Methods that a class doesn't have itself are inherited from its
+    // superclass (and its superclass, and so on)
+    fn find_method(&self, name : &str) -> Option<&Rc<LoxFunction>> {
+    self.methods.get(name).or_else(|| self.superclass.as_ref()?.find_method(name))
+    }
+}
+
+// An instance of a class.  Fields can be added to an instance at any time
+// (and can be changed through any reference to the instance), so they're in
+// a RefCell just like the variables in an Environment.
+pub struct LoxInstance {
+    pub class : Rc<LoxClass>,
+    pub fields : RefCell<HashMap<String, LoxValue>>,
+}
+
+impl LoxInstance {
+    // Look up obj.name.  A field wins over a method with the same name.
+    fn get(instance : &Rc<LoxInstance>, name : &str) -> Option<LoxValue> {
+    if let Some(value) = instance.fields.borrow().get(name) {
+        Some(value.clone())
+    } else {
+        let method = instance.class.find_method(name)?;
+        Some(LFunction(Rc::new(method.bind(instance))))
+    }
+    }
+    fn set(&self, name : &str, value : LoxValue) {
+    self.fields.borrow_mut().insert(name.to_string(), value);
+    }
+}
+
+// Like functions, classes and instances are only equal to themselves and
+// are kept short when debug printed.
+impl PartialEq for LoxClass {
+    fn eq(&self, other : &LoxClass) -> bool {
+    std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<class {}>", self.name)
+    }
+}
+
+impl PartialEq for LoxInstance {
+    fn eq(&self, other : &LoxInstance) -> bool {
+    std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<{} instance>", self.class.name)
+    }
 }
 
 use LoxValue::*;
 
-pub fn interpret_statements(statements : &Statements, environ : &Rc<Environment>) -> () {
+// How values look when they're printed or put into a string.  Numbers with
+// no fractional part don't get a ".0" (so 3, not 3.0).  Infinity is spelled
+// out like in the reference Lox (Rust would write "inf").
+impl fmt::Display for LoxValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+        LNumber(value) if value.is_infinite() => write!(f, "{}Infinity", if *value < 0.0 { "-" } else { "" }),
+        LNumber(value) => write!(f, "{value}"),
+        LString(value) => write!(f, "{value}"),
+        LBoolean(value) => write!(f, "{value}"),
+        LNil => write!(f, "nil"),
+        LFunction(function) => write!(f, "<fn {}>", function.declaration.name),
+        LClass(class) => write!(f, "{}", class.name),
+        LInstance(instance) => write!(f, "{} instance", instance.class.name),
+    }
+    }
+}
+
+impl LoxValue {
+    // Name of the value's type (used in error messages)
+    pub fn type_name(&self) -> &'static str {
+    match self {
+        LNumber(_) => "number",
+        LString(_) => "string",
+        LBoolean(_) => "boolean",
+        LNil => "nil",
+        LFunction(_) => "function",
+        LClass(_) => "class",
+        LInstance(_) => "instance",
+    }
+    }
+}
+
+// Discussion: Something going wrong while the program runs (adding a number
+// to a string, using an undefined variable, etc.) used to be a panic!(),
+// which takes down the whole process.  Instead, errors are values that get
+// passed back up through every interpret_* function using Result and the ?
+// operator.  The error records where it happened (the span of the node that
+// failed) and, for bad operations, the operator and the types of the values
+// involved.
+#[derive(PartialEq, Debug)]
+pub struct RuntimeError {
+    pub message : String,
+    pub span : Span,
+    pub operator : Option<Op>,          // Offending operator (if any)
+    pub operands : Vec<&'static str>,   // Types of the operands involved
+}
+
+impl RuntimeError {
+    pub fn new(message : &str, span : Span) -> RuntimeError {
+    RuntimeError { message : String::from(message), span, operator : None, operands : Vec::new() }
+    }
+    // An operator was applied to values of the wrong type
+    pub fn unsupported(op : &Op, operands : &[&LoxValue], span : Span) -> RuntimeError {
+    RuntimeError {
+        message : String::from("Unsupported operation"),
+        span,
+        operator : Some(*op),
+        operands : operands.iter().map(|v| v.type_name()).collect(),
+    }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "[line {}] Error: {}", self.span.line, self.message)?;
+    match (&self.operator, self.operands.as_slice()) {
+        (Some(op), [left, right]) => write!(f, ": {left} {op} {right}"),
+        (Some(op), [operand]) => write!(f, ": {op}{operand}"),
+        _ => Ok(())
+    }
+    }
+}
+
+// Discussion: Most statements just run and then carry on with the next
+// statement.  But a return has to jump out of whatever blocks and loops it's
+// in, all the way back to the function call.  That's not an error, so it
+// doesn't go through Err (and a panic!() would be worse).  Instead, every
+// interpret_statement says how it finished.  A statement that contains
+// other statements (block, if, while) stops and passes anything other than
+// Normal straight back up to its caller.  The loop catches Break and
+// Continue.  Return goes on up to the function call.  (The parser makes
+// sure that break and continue are only used in loops and that return is
+// only used in functions.  A tree that came from somewhere else might not
+// follow those rules, so anything that gets out to where it doesn't belong
+// is an error.)
+#[derive(PartialEq, Debug)]
+pub enum Flow {
+    Normal,              // Carry on with the next statement
+    Return(LoxValue),    // Return from the current function
+    Break,               // Leave the current loop
+    Continue,            // Go on to the next time around the current loop
+}
+
+// Only Normal is allowed to get out of a function body or a whole program
+fn check_flow(flow : Flow, span : Span) -> Result<(), RuntimeError> {
+    match flow {
+    Flow::Normal => Ok(()),
+    Flow::Return(_) => Err(RuntimeError::new("Can't return from top-level code.", span)),
+    Flow::Break => Err(RuntimeError::new("Can't use 'break' outside of a loop.", span)),
+    Flow::Continue => Err(RuntimeError::new("Can't use 'continue' outside of a loop.", span)),
+    }
+}
+
+pub fn interpret_statements(statements : &Statements, environ : &Rc<Environment>) -> Result<Flow, RuntimeError> {
     for stmt in statements.iter() {
-    interpret_statement(stmt, environ);
+    let flow = interpret_statement(stmt, environ)?;
+    if flow != Flow::Normal {
+        return Ok(flow);
     }
+    }
+    Ok(Flow::Normal)
 }
 
-pub fn interpret_statement(stmt : &Statement, environ : &Rc<Environment>) -> () {
-    match stmt {
+pub fn interpret_statement(stmt : &Stmt, environ : &Rc<Environment>) -> Result<Flow, RuntimeError> {
+    match &stmt.node {
     SPrint(value) => {
-        let lvalue = interpret_expression(value, environ);
-        // Note: This will need to be refined later for the final language.
-        // I've modified the print so it appears as something very obvious.
-        println!("LOX: {lvalue:?}");
+        let lvalue = interpret_expression(value, environ)?;
+        println!("{lvalue}");
     },
     SExpr(value) => {
-        interpret_expression(value, environ);
+        interpret_expression(value, environ)?;
     },
     SVar(name, value) => {
-        let lvalue = interpret_expression(value, environ);
+        let lvalue = interpret_expression(value, environ)?;
         environ.define(name, lvalue);
     },
     SIf(test, consequence, alternative) => {
-        let tvalue = interpret_expression(test, environ);
+        let tvalue = interpret_expression(test, environ)?;
         if is_truthy(&tvalue) {
-        interpret_statement(consequence, environ);
-        } else {
-        interpret_statement(alternative, environ);
+        return interpret_statement(consequence, environ);
+        } else if let Some(alternative) = alternative {
+        return interpret_statement(alternative, environ);
         }
     },
-    SWhile(test, body) => {
-        while is_truthy(&interpret_expression(test, environ)) {
-        interpret_statement(body, environ);
+    SWhile(test, body, increment) => {
+        while is_truthy(&interpret_expression(test, environ)?) {
+        match interpret_statement(body, environ)? {
+            Flow::Break => break,
+            Flow::Normal | Flow::Continue => { },
+            flow => return Ok(flow),
+        }
+        if let Some(increment) = increment {
+            interpret_statement(increment, environ)?;
         }
-    },
-    SAssignment(location, body) => {
-        match location {
-        EName(name) => environ.set(name, interpret_expression(body, environ)),
-        _ => panic!("Can't assign to that")
         }
     },
     SBlock(statements) => {
-        interpret_statements(statements, &Environment::new_scope(environ))
+        return interpret_statements(statements, &Environment::new_scope(environ));
+    },
+    SFunction(function) => {
+        let closure = LoxFunction { declaration : function.clone(), closure : environ.clone(), is_initializer : false };
+        environ.define(&function.name, LFunction(Rc::new(closure)));
+    },
+    SClass(class) => {
+        let superclass = match &class.superclass {
+        Some(expr) => match interpret_expression(expr, environ)? {
+            LClass(superclass) => Some(superclass),
+            _ => return Err(RuntimeError::new("Superclass must be a class.", expr.span))
+        },
+        None => None,
+        };
+        // With a superclass, the methods get an extra scope where "super" is
+        // the superclass.  (A bound method's "this" scope goes inside that.)
+        let closure = match &superclass {
+        Some(superclass) => {
+            let scope = Environment::new_scope(environ);
+            scope.define("super", LClass(superclass.clone()));
+            scope
+        },
+        None => environ.clone(),
+        };
+        let mut methods = HashMap::new();
+        for Spanned { node : method, .. } in class.methods.iter() {
+        let function = LoxFunction {
+            declaration : method.clone(),
+            closure : closure.clone(),
+            is_initializer : method.name == "init",
+        };
+        methods.insert(method.name.clone(), Rc::new(function));
+        }
+        let lclass = LoxClass { name : class.name.clone(), superclass, methods };
+        environ.define(&class.name, LClass(Rc::new(lclass)));
+    },
+    SReturn(value) => {
+        let lvalue = match value {
+        Some(value) => interpret_expression(value, environ)?,
+        None => LNil,
+        };
+        return Ok(Flow::Return(lvalue));
+    },
+    SBreak => {
+        return Ok(Flow::Break);
+    },
+    SContinue => {
+        return Ok(Flow::Continue);
     }
     }
+    Ok(Flow::Normal)
 }
 
 fn is_truthy(lvalue : &LoxValue) -> bool {
     // See section 7.2.4
-    match lvalue {
-    LBoolean(false) | LNil => false,
-    _ => true
-    }
+    !matches!(lvalue, LBoolean(false) | LNil)
 }
 // Tree-walk interpreter (simplest thing you can do, but not fastest)
-pub fn interpret_expression(expr : &Expression, environ : &Rc<Environment>) -> LoxValue {
-    match expr {
+pub fn interpret_expression(expr : &Expr, environ : &Rc<Environment>) -> Result<LoxValue, RuntimeError> {
+    let value = match &expr.node {
     ENumber(value) => {
         LNumber(*value)       // In AST, value was already f64
     },
@@ -99,12 +363,12 @@ pub fn interpret_expression(expr : &Expression, environ : &Rc<Environment>) -> L
         if let Some(lvalue) = environ.lookup(name) {
         lvalue
         } else {
-        panic!("Undefined variable name")
+        return Err(RuntimeError::new(&format!("Undefined variable '{name}'."), expr.span))
         }
     }
     EBinary(op, left, right) => {
-        let leftval = interpret_expression(left, environ);
-        let rightval = interpret_expression(right, environ);
+        let leftval = interpret_expression(left, environ)?;
+        let rightval = interpret_expression(right, environ)?;
         match (leftval, op, rightval) {
         // Numeric operations
         (LNumber(lv), OpPlus, LNumber(rv)) => { LNumber(lv+rv) },
@@ -115,46 +379,612 @@ pub fn interpret_expression(expr : &Expression, environ : &Rc<Environment>) -> L
         (LNumber(lv), OpLe, LNumber(rv)) => { LBoolean(lv <= rv) },
         (LNumber(lv), OpGt, LNumber(rv)) => { LBoolean(lv > rv) },
         (LNumber(lv), OpGe, LNumber(rv)) => { LBoolean(lv >= rv) },
-        (LNumber(lv), OpEq, LNumber(rv)) => { LBoolean(lv == rv) },
-        (LNumber(lv), OpNe, LNumber(rv)) => { LBoolean(lv != rv) },
         // String operations
         (LString(lv), OpPlus, LString(rv)) => { LString(lv+&rv) },
-        (LString(lv), OpEq, LString(rv)) => { LBoolean(lv == rv) },
-        (LString(lv), OpNe, LString(rv)) => { LBoolean(lv != rv) },
-        // Boolean operations
-        (LBoolean(lv), OpEq, LBoolean(rv)) => { LBoolean(lv == rv) },
-        (LBoolean(lv), OpNe, LBoolean(rv)) => { LBoolean(lv != rv) },
+        // Any two values can be compared for equality (see section 7.2.5).
+        // Values of different types are never equal, nil == nil, and
+        // functions, classes and instances are only equal to themselves.
+        (lv, OpEq, rv) => { LBoolean(lv == rv) },
+        (lv, OpNe, rv) => { LBoolean(lv != rv) },
 
-        _ => {
+        (leftval, op, rightval) => {
             // 34 + "hello"
-            panic!("Unsupported operation")
+            return Err(RuntimeError::unsupported(op, &[&leftval, &rightval], expr.span))
         }
         }
     },
+    ELogical(op, left, right) => {
+        // The right side only gets evaluated if the left side doesn't decide
+        // the answer.  The result is whichever operand decided it (not
+        // necessarily true/false), so "nil or 2" is 2.
+        let leftval = interpret_expression(left, environ)?;
+        match (op, is_truthy(&leftval)) {
+        (OpOr, true) | (OpAnd, false) => leftval,
+        _ => interpret_expression(right, environ)?,
+        }
+    },
     EGroup(value) => {
-        interpret_expression(value, environ)
+        interpret_expression(value, environ)?
     },
     EUnary(op, value) => {
-        let lvalue = interpret_expression(value, environ);
+        let lvalue = interpret_expression(value, environ)?;
         match (op, lvalue) {
         (OpMinus, LNumber(v)) => { LNumber(-v) },
 
-        // What is "truthy"?
-        (OpNot, LBoolean(v)) => { LBoolean(!v) },
-        _ => {
-            panic!("Unsupported operation")
+        (OpNot, v) => { LBoolean(!is_truthy(&v)) },
+        (op, lvalue) => {
+            return Err(RuntimeError::unsupported(op, &[&lvalue], expr.span))
+        }
+        }
+    },
+    ECall(callee, arguments) => {
+        let calleeval = interpret_expression(callee, environ)?;
+        let mut argvals = Vec::new();
+        for arg in arguments {
+        argvals.push(interpret_expression(arg, environ)?);
         }
+        match calleeval {
+        LFunction(function) => call_function(&function, argvals, expr.span)?,
+        LClass(class) => instantiate(&class, argvals, expr.span)?,
+        _ => return Err(RuntimeError::new("Can only call functions and classes.", expr.span))
         }
+    },
+    EGet(object, name) => {
+        let LInstance(instance) = interpret_expression(object, environ)? else {
+        return Err(RuntimeError::new("Only instances have properties.", object.span))
+        };
+        match LoxInstance::get(&instance, name) {
+        Some(value) => value,
+        None => return Err(RuntimeError::new(&format!("Undefined property '{name}'."), expr.span))
+        }
+    },
+    EAssign(target, value) => {
+        match &target.node {
+        EName(name) => {
+            let lvalue = interpret_expression(value, environ)?;
+            environ.set(name, lvalue.clone()).map_err(|msg| RuntimeError::new(&msg, target.span))?;
+            lvalue
+        },
+        EGet(object, name) => {
+            let LInstance(instance) = interpret_expression(object, environ)? else {
+            return Err(RuntimeError::new("Only instances have fields.", object.span))
+            };
+            let lvalue = interpret_expression(value, environ)?;
+            instance.set(name, lvalue.clone());
+            lvalue
+        },
+        // The parser never builds these, but an AST can come from elsewhere
+        _ => return Err(RuntimeError::new("Invalid assignment target.", target.span))
+        }
+    },
+    EThis => {
+        // The parser made sure "this" is only used inside a class, so a
+        // method's closure always has it.  A tree loaded from JSON hasn't
+        // been checked, though.
+        match environ.lookup("this") {
+        Some(value) => value,
+        None => return Err(RuntimeError::new("Can't use 'this' outside of a class.", expr.span))
+        }
+    },
+    ESuper(name) => {
+        // super.name is the superclass's method, bound to this instance.
+        // Again, "super" and "this" are around unless the tree is bad.
+        let (Some(LClass(superclass)), Some(LInstance(instance))) = (environ.lookup("super"), environ.lookup("this")) else {
+        return Err(RuntimeError::new("Can't use 'super' outside of a subclass.", expr.span))
+        };
+        match superclass.find_method(name) {
+        Some(method) => LFunction(Rc::new(method.bind(&instance))),
+        None => return Err(RuntimeError::new(&format!("Undefined property '{name}'."), expr.span))
+        }
+    },
+    EInterpolate(parts, tail) => {
+        let mut result = String::new();
+        for (text, value) in parts {
+        result.push_str(text);
+        result.push_str(&interpret_expression(value, environ)?.to_string());
+        }
+        result.push_str(tail);
+        LString(result)
     }
+    };
+    Ok(value)
+}
+
+// Discussion: Every Lox call is several nested Rust calls (interpret_statements,
+// interpret_statement, interpret_expression, call_function, ...), all on the
+// Rust stack.  A Lox program that recurses forever would use up that stack,
+// and Rust doesn't turn that into an error.  It aborts the whole process.
+// So the interpreter counts how deep the Lox calls go and gives up with a
+// RuntimeError well before the Rust stack runs out.  The count isn't part of
+// any environment (a function's closure says nothing about who called it),
+// so it's kept per thread.
+//
+// How much stack a Lox call takes depends on the build (a debug build uses
+// a lot more) and on how complicated the function is.  The main thread's
+// stack (8MB on Linux, only 1MB on Windows) isn't enough for a useful
+// depth, so the program should be run on a thread with STACK_SIZE bytes
+// of stack (see main.rs).  The memory for a thread's stack is only used as
+// it's needed, so asking for a big one is cheap.
+pub const MAX_CALL_DEPTH : usize = 1000;
+pub const STACK_SIZE : usize = 256 * 1024 * 1024;
+
+thread_local! {
+    static CALL_DEPTH : Cell<usize> = const { Cell::new(0) };
+}
+
+// Call a function.  The parameters become variables in a brand new scope
+// (one per call, so recursion works) inside the function's closure.
+fn call_function(function : &LoxFunction, args : Vec<LoxValue>, span : Span) -> Result<LoxValue, RuntimeError> {
+    let declaration = &function.declaration;
+    if args.len() != declaration.params.len() {
+    return Err(RuntimeError::new(&format!("Expected {} arguments but got {}.", declaration.params.len(), args.len()), span));
+    }
+    let depth = CALL_DEPTH.get();
+    if depth >= MAX_CALL_DEPTH {
+    return Err(RuntimeError::new("Stack overflow.", span));
+    }
+    let scope = Environment::new_scope(&function.closure);
+    for (param, arg) in declaration.params.iter().zip(args) {
+    scope.define(param, arg);
+    }
+    CALL_DEPTH.set(depth + 1);
+    let flow = interpret_statements(&declaration.body, &scope);
+    CALL_DEPTH.set(depth);
+    let value = match flow? {
+    Flow::Return(value) => value,
+    flow => {
+        check_flow(flow, span)?;
+        LNil
+    },
+    };
+    if function.is_initializer {
+    // init() hands back the instance, even if called directly as obj.init()
+    return Ok(function.closure.lookup("this").expect("initializer without this"));
+    }
+    Ok(value)
+}
+
+// Call a class to make a new instance.  The arguments go to init() (if
+// there is one).
+fn instantiate(class : &Rc<LoxClass>, args : Vec<LoxValue>, span : Span) -> Result<LoxValue, RuntimeError> {
+    let instance = Rc::new(LoxInstance { class : class.clone(), fields : RefCell::new(HashMap::new()) });
+    match class.find_method("init") {
+    Some(init) => {
+        call_function(&init.bind(&instance), args, span)?;
+    },
+    None if !args.is_empty() => {
+        return Err(RuntimeError::new(&format!("Expected 0 arguments but got {}.", args.len()), span));
+    },
+    None => { }
     }
+    Ok(LInstance(instance))
 }
 
+#[cfg(test)]
+use crate::parse::{parse, parse_expression_string, parse_statement_string};
+
+
 #[test]
 fn test_interpret() {
     let expr = parse_expression_string("2 + 3 * 4");
-    assert_eq!(interpret_expression(&expr, &Environment::new()), LNumber(14.0));
+    assert_eq!(interpret_expression(&expr, &Environment::new()), Ok(LNumber(14.0)));
     let expr = parse_expression_string("(2 + 3) * (4 + 5)");
-    assert_eq!(interpret_expression(&expr, &Environment::new()), LNumber(45.0));
+    assert_eq!(interpret_expression(&expr, &Environment::new()), Ok(LNumber(45.0)));
     let expr = parse_expression_string("(2 + 3) < (4 + 5)");
-    assert_eq!(interpret_expression(&expr, &Environment::new()), LBoolean(true));
+    assert_eq!(interpret_expression(&expr, &Environment::new()), Ok(LBoolean(true)));
+    // Equality works on any two values, and anything can be negated
+    let eval = |src : &str| interpret_expression(&parse_expression_string(src), &Environment::new());
+    assert_eq!(eval("nil == nil"), Ok(LBoolean(true)));
+    assert_eq!(eval("\"a\" != nil"), Ok(LBoolean(true)));
+    assert_eq!(eval("1 == \"1\""), Ok(LBoolean(false)));
+    assert_eq!(eval("false == nil"), Ok(LBoolean(false)));
+    assert_eq!(eval("!nil"), Ok(LBoolean(true)));
+    assert_eq!(eval("!0"), Ok(LBoolean(false)));
+}
+
+#[cfg(test)]
+// Run a program and then evaluate an expression in its global scope
+fn run_then_eval(src : &str, expr : &str) -> Result<LoxValue, RuntimeError> {
+    let env = Environment::new();
+    interpret_statements(&parse(src).expect("syntax error"), &env)?;
+    interpret_expression(&parse_expression_string(expr), &env)
+}
+
+#[test]
+fn test_functions() {
+    let src = "
+fun add(a, b) { return a + b; }
+fun fib(n) {
+  if n < 2 { return n; } else { }
+  return fib(n - 1) + fib(n - 2);
+}
+fun nothing() { }
+fun early(n) {
+  while true {
+    { if n > 3 { return \"big\"; } else { } }
+    n = n + 1;
+  }
+}
+var n = 100;
+fun shadow(n) { n = n + 1; return n; }
+";
+    assert_eq!(run_then_eval(src, "add(1, 2)"), Ok(LNumber(3.0)));
+    assert_eq!(run_then_eval(src, "fib(15)"), Ok(LNumber(610.0)));
+    assert_eq!(run_then_eval(src, "nothing()"), Ok(LNil));
+    assert_eq!(run_then_eval(src, "early(1)"), Ok(LString(String::from("big"))));
+    // Each call gets its own scope for its parameters
+    assert_eq!(run_then_eval(src, "shadow(1) + n"), Ok(LNumber(102.0)));
+    assert_eq!(run_then_eval(src, "\"${add}\""), Ok(LString(String::from("<fn add>"))));
+
+    let err = run_then_eval(src, "add(1)").unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Expected 2 arguments but got 1.");
+    let err = run_then_eval(src, "\n\"add\"(1, 2)").unwrap_err();
+    assert_eq!(err.to_string(), "[line 2] Error: Can only call functions and classes.");
+    assert_eq!(err.span, Span::new(1, 12, 2, 1));
+    // Errors inside a function still point at the spot in the function
+    let err = run_then_eval(src, "add(1, nil)").unwrap_err();
+    assert_eq!(err.to_string(), "[line 2] Error: Unsupported operation: number + nil");
+    // A function's parameters aren't visible to the code that called it
+    let err = run_then_eval("fun f(a) { return g(); } fun g() { return a; }", "f(1)").unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Undefined variable 'a'.");
+}
+
+#[test]
+fn test_closures() {
+    let src = "
+fun make_counter() {
+  var count = 0;
+  fun counter() {
+    count = count + 1;
+    return count;
+  }
+  return counter;
+}
+var a = make_counter();
+var b = make_counter();
+a(); a();
+var from_a = a();
+var from_b = b();
+
+fun make_adder(n) {
+  fun adder(x) { return x + n; }
+  return adder;
+}
+var add10 = make_adder(10);
+
+var nested;
+{
+  var outer = \"outer\";
+  fun middle() {
+    var inner = \"inner\";
+    fun innermost() { return outer + \" \" + inner; }
+    return innermost;
+  }
+  nested = middle();
+}
+
+var global = \"before\";
+fun peek() { return global; }
+global = \"after\";
+";
+    // Each counter has its own count, which lives on after make_counter() returns
+    assert_eq!(run_then_eval(src, "from_a"), Ok(LNumber(3.0)));
+    assert_eq!(run_then_eval(src, "from_b"), Ok(LNumber(1.0)));
+    assert_eq!(run_then_eval(src, "add10(5)"), Ok(LNumber(15.0)));
+    assert_eq!(run_then_eval(src, "make_adder(1)(2)"), Ok(LNumber(3.0)));
+    // The block and middle() are both long gone, but their variables aren't
+    assert_eq!(run_then_eval(src, "nested()"), Ok(LString(String::from("outer inner"))));
+    // Closures capture variables, not values
+    assert_eq!(run_then_eval(src, "peek()"), Ok(LString(String::from("after"))));
+    // Closed-over variables stay out of sight of everyone else
+    let err = run_then_eval(src, "count").unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Undefined variable 'count'.");
+    // Function values are equal only to themselves
+    assert_eq!(run_then_eval(src, "a == a"), Ok(LBoolean(true)));
+    assert_eq!(run_then_eval(src, "a == b"), Ok(LBoolean(false)));
+}
+
+#[test]
+fn test_classes() {
+    let src = "
+class Counter {
+  init(start) {
+    this.count = start;
+    if start < 0 { return; } else { }
+    this.count = this.count * 10;
+  }
+  add(n) {
+    this.count = this.count + n;
+    return this;
+  }
+  get() { return this.count; }
+}
+var c = Counter(1);
+c.add(2).add(3);
+var after_adds = c.get();
+var method = c.get;
+var again = c.init(4);
+
+class Empty { }
+var e = Empty();
+e.field = \"set\";
+e.method = method;
+
+class Nested {
+  make() {
+    fun inner() { return this; }
+    return inner;
+  }
+}
+var n = Nested();
+";
+    assert_eq!(run_then_eval(src, "after_adds"), Ok(LNumber(15.0)));
+    assert_eq!(run_then_eval(src, "c.count"), Ok(LNumber(40.0)));
+    assert_eq!(run_then_eval(src, "Counter(-1).get()"), Ok(LNumber(-1.0)));
+    // A method taken off an instance stays bound to it
+    assert_eq!(run_then_eval(src, "method()"), Ok(LNumber(40.0)));
+    assert_eq!(run_then_eval(src, "again == c"), Ok(LBoolean(true)));
+    assert_eq!(run_then_eval(src, "e.field + \" \" + \"${e.method()}\""), Ok(LString(String::from("set 40"))));
+    assert_eq!(run_then_eval(src, "n.make()() == n"), Ok(LBoolean(true)));
+    assert_eq!(run_then_eval(src, "\"${Counter} ${c} ${c.get}\""), Ok(LString(String::from("Counter Counter instance <fn get>"))));
+    assert_eq!(run_then_eval(src, "Empty() == Empty()"), Ok(LBoolean(false)));
+
+    let message = |expr : &str| run_then_eval(src, expr).unwrap_err().to_string();
+    assert_eq!(message("e.missing"), "[line 1] Error: Undefined property 'missing'.");
+    assert_eq!(message("Counter()"), "[line 1] Error: Expected 1 arguments but got 0.");
+    assert_eq!(message("Empty(1)"), "[line 1] Error: Expected 0 arguments but got 1.");
+    assert_eq!(message("c.count.x"), "[line 1] Error: Only instances have properties.");
+    let err = run_then_eval("var x = 1;\nx.y = 2;", "x").unwrap_err();
+    assert_eq!(err.to_string(), "[line 2] Error: Only instances have fields.");
+}
+
+#[test]
+fn test_inheritance() {
+    let src = "
+class Animal {
+  init(name) { this.name = name; }
+  speak() { return this.name + \" makes a sound\"; }
+  describe() { return \"${this.speak()}!\"; }
+}
+class Dog < Animal {
+  speak() { return this.name + \" barks\"; }
+  parent() { return super.speak(); }
+}
+class Puppy < Dog {
+  init(name) { super.init(\"little \" + name); }
+  speak() { return super.speak() + \" softly\"; }
+}
+var d = Dog(\"Rex\");
+var p = Puppy(\"Bit\");
+var NotAClass = 1;
+";
+    // Inherited init() and describe(), overridden speak()
+    assert_eq!(run_then_eval(src, "d.describe()"), Ok(LString(String::from("Rex barks!"))));
+    assert_eq!(run_then_eval(src, "d.parent()"), Ok(LString(String::from("Rex makes a sound"))));
+    // super goes to the superclass of the class the method is in (not of the instance)
+    assert_eq!(run_then_eval(src, "p.speak()"), Ok(LString(String::from("little Bit barks softly"))));
+    assert_eq!(run_then_eval(src, "p.parent()"), Ok(LString(String::from("little Bit makes a sound"))));
+    assert_eq!(run_then_eval(src, "p.describe()"), Ok(LString(String::from("little Bit barks softly!"))));
+
+    let err = run_then_eval("class A { }\nclass B < A { f() { return super.g(); } }", "B().f()").unwrap_err();
+    assert_eq!(err.to_string(), "[line 2] Error: Undefined property 'g'.");
+    let err = run_then_eval(&format!("{src}class Bad < NotAClass {{ }}"), "1").unwrap_err();
+    assert_eq!(err.to_string(), "[line 18] Error: Superclass must be a class.");
+}
+
+#[test]
+fn test_for() {
+    let src = "
+var total = 0;
+for (var i = 0; i < 5; i = i + 1) {
+  total = total + i;
+}
+var i = \"outer\";
+for (var i = 10; i > 0; i = i - 3) { }
+var count = 0;
+for (; count < 4;) count = count + 1;
+var j = 0;
+for (j = 1; j < 100; ) j = j * 2;
+";
+    assert_eq!(run_then_eval(src, "total"), Ok(LNumber(10.0)));
+    // The loop variable stays inside the loop
+    assert_eq!(run_then_eval(src, "i"), Ok(LString(String::from("outer"))));
+    assert_eq!(run_then_eval(src, "count"), Ok(LNumber(4.0)));
+    assert_eq!(run_then_eval(src, "j"), Ok(LNumber(128.0)));
+    let err = run_then_eval("for (var i = 0; i < 3; i = i + 1) { }\nprint i;", "1").unwrap_err();
+    assert_eq!(err.to_string(), "[line 2] Error: Undefined variable 'i'.");
+}
+
+#[test]
+fn test_break() {
+    let src = "
+var found = nil;
+for (var i = 0; i < 10; i = i + 1) {
+  if i * i > 20 { found = i; break; } else { }
+}
+var odds = 0;
+var even = false;
+for (var i = 0; i < 10; i = i + 1) {
+  even = !even;
+  if even { continue; } else { }
+  odds = odds + i;
+}
+fun first_over(limit) {
+  var n = 0;
+  while true {
+    while true {
+      n = n + 1;
+      if n < limit { continue; } else { }
+      break;
+    }
+    if n > 100 { return \"never\"; } else { }
+    { return n; }
+  }
+}
+var pairs = 0;
+for (var a = 0; a < 4; a = a + 1) {
+  for (var b = 0; b < 4; b = b + 1) {
+    if b == a { break; } else { }
+    pairs = pairs + 1;
+  }
+}
+";
+    assert_eq!(run_then_eval(src, "found"), Ok(LNumber(5.0)));
+    // continue still runs the for-loop increment (otherwise this never ends)
+    assert_eq!(run_then_eval(src, "odds"), Ok(LNumber(25.0)));
+    assert_eq!(run_then_eval(src, "first_over(7)"), Ok(LNumber(7.0)));
+    // break only leaves the innermost loop
+    assert_eq!(run_then_eval(src, "pairs"), Ok(LNumber(6.0)));
+    // Errors go straight through loops
+    let err = run_then_eval("while true { if true { nope; } else { break; } }", "1").unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Undefined variable 'nope'.");
+
+    // A tree that didn't come from the parser can have them anywhere.  They
+    // can't quietly end the program or the function.
+    let span = Span::new(0, 6, 3, 1);
+    let program = |stmt : Stmt| vec![SPrint(ENil.into()).into(), Spanned::new(SBlock(vec![stmt]), span)];
+    let err = interpret(&program(SBreak.into())).unwrap_err();
+    assert_eq!(err.to_string(), "[line 3] Error: Can't use 'break' outside of a loop.");
+    let err = interpret(&program(SReturn(None).into())).unwrap_err();
+    assert_eq!(err.to_string(), "[line 3] Error: Can't return from top-level code.");
+    let function = Function { name : String::from("f"), params : vec![], body : vec![SContinue.into()] };
+    let env = Environment::new();
+    interpret_statement(&SFunction(Rc::new(function)).into(), &env).unwrap();
+    let err = interpret_expression(&Spanned::new(ECall(Box::new(EName(String::from("f")).into()), vec![]), span), &env).unwrap_err();
+    assert_eq!(err.to_string(), "[line 3] Error: Can't use 'continue' outside of a loop.");
+}
+
+#[test]
+fn test_if() {
+    let src = "
+fun classify(n) {
+  if n < 0 { return \"negative\"; }
+  else if n == 0 { return \"zero\"; }
+  else if n < 10 { return \"small\"; }
+  else { return \"large\"; }
+}
+var touched = false;
+if false touched = true;
+var nested = 0;
+if true if false nested = 1; else nested = 2;
+";
+    assert_eq!(run_then_eval(src, "classify(-3)"), Ok(LString(String::from("negative"))));
+    assert_eq!(run_then_eval(src, "classify(0)"), Ok(LString(String::from("zero"))));
+    assert_eq!(run_then_eval(src, "classify(5)"), Ok(LString(String::from("small"))));
+    assert_eq!(run_then_eval(src, "classify(50)"), Ok(LString(String::from("large"))));
+    // Without an else, a false test does nothing
+    assert_eq!(run_then_eval(src, "touched"), Ok(LBoolean(false)));
+    assert_eq!(run_then_eval(src, "nested"), Ok(LNumber(2.0)));
+}
+
+#[test]
+fn test_assignment() {
+    let src = "
+var a = 1;
+var b = 2;
+a = b = 7;
+var lines = 0;
+var next = 3;
+fun next_line() {
+  next = next - 1;
+  if next > 0 { return \"line\"; } else { return nil; }
+}
+var line;
+while ((line = next_line()) != nil) { lines = lines + 1; }
+class Box { }
+var box = Box();
+var copy = box.value = \"set\";
+";
+    assert_eq!(run_then_eval(src, "a"), Ok(LNumber(7.0)));
+    assert_eq!(run_then_eval(src, "b"), Ok(LNumber(7.0)));
+    assert_eq!(run_then_eval(src, "lines"), Ok(LNumber(2.0)));
+    assert_eq!(run_then_eval(src, "copy"), Ok(LString(String::from("set"))));
+    assert_eq!(run_then_eval(src, "box.value"), Ok(LString(String::from("set"))));
+    // The value of an assignment is the value assigned
+    assert_eq!(run_then_eval(src, "a = a + 1"), Ok(LNumber(8.0)));
+}
+
+#[test]
+fn test_logical() {
+    let env = Environment::new();
+    let eval = |src : &str| interpret_expression(&parse_expression_string(src), &env);
+    assert_eq!(eval("1 < 2 and 3 < 4"), Ok(LBoolean(true)));
+    assert_eq!(eval("nil or \"yes\""), Ok(LString(String::from("yes"))));
+    assert_eq!(eval("0 or 2"), Ok(LNumber(0.0)));
+    assert_eq!(eval("false and 2"), Ok(LBoolean(false)));
+    assert_eq!(eval("1 and nil"), Ok(LNil));
+    assert_eq!(eval("nil and 1 or 3"), Ok(LNumber(3.0)));
+    // Short-circuiting: the undefined variable is never looked at
+    assert_eq!(eval("true or undefined"), Ok(LBoolean(true)));
+    assert_eq!(eval("nil and undefined"), Ok(LNil));
+    let err = eval("false or undefined").unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Undefined variable 'undefined'.");
+}
+
+#[test]
+fn test_interpolation() {
+    let env = Environment::new();
+    env.define("n", LNumber(21.0));
+    env.define("name", LString(String::from("Lox")));
+    let eval = |src : &str| interpret_expression(&parse_expression_string(src), &env);
+    assert_eq!(eval("\"total = ${n * 2}\""), Ok(LString(String::from("total = 42"))));
+    assert_eq!(eval("\"${n / 2}|${name}|${n > 1}|${nil}\""), Ok(LString(String::from("10.5|Lox|true|nil"))));
+    assert_eq!(eval("\"a ${\"b ${name} c\"} d\""), Ok(LString(String::from("a b Lox c d"))));
+    assert_eq!(eval("\"${1}${2}\" + \"!\""), Ok(LString(String::from("12!"))));
+    assert_eq!(eval("\"cost: \\${n}\""), Ok(LString(String::from("cost: ${n}"))));
+    let err = eval("\"x ${missing}\"").unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Undefined variable 'missing'.");
+
+    assert_eq!(LNumber(-0.5).to_string(), "-0.5");
+    assert_eq!(LNumber(1e6).to_string(), "1000000");
+    assert_eq!(eval("\"${1 / 0} ${-1 / 0} ${0 / 0}\""), Ok(LString(String::from("Infinity -Infinity NaN"))));
+    assert_eq!(LBoolean(false).to_string(), "false");
+}
+
+#[test]
+fn test_runtime_errors() {
+    let env = Environment::new();
+    let err = interpret_expression(&parse_expression_string("2 +\ntrue"), &env).unwrap_err();
+    assert_eq!(err.span, Span::new(0, 8, 1, 1));
+    assert_eq!(err.operator, Some(OpPlus));
+    assert_eq!(err.operands, vec!["number", "boolean"]);
+    assert_eq!(err.to_string(), "[line 1] Error: Unsupported operation: number + boolean");
+
+    let err = interpret_expression(&parse_expression_string("-true"), &env).unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Unsupported operation: -boolean");
+
+    let err = interpret_expression(&parse_expression_string("\n\nxyz"), &env).unwrap_err();
+    assert_eq!(err.to_string(), "[line 3] Error: Undefined variable 'xyz'.");
+
+    let err = interpret_statement(&parse_statement_string("\nxyz = 2;"), &env).unwrap_err();
+    assert_eq!(err.to_string(), "[line 2] Error: Undefined variable 'xyz'.");
+    assert_eq!(err.span, Span::new(1, 4, 2, 1));
+
+    let bad = EAssign(Box::new(Spanned::new(ENumber(1.0), Span::new(0, 1, 1, 1))), Box::new(ENumber(2.0).into())).into();
+    let err = interpret_expression(&bad, &env).unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] Error: Invalid assignment target.");
+    // A tree that didn't come from the parser can have "this" anywhere
+    let err = interpret_expression(&EThis.into(), &env).unwrap_err();
+    assert_eq!(err.message, "Can't use 'this' outside of a class.");
+    let err = interpret_expression(&ESuper(String::from("m")).into(), &env).unwrap_err();
+    assert_eq!(err.message, "Can't use 'super' outside of a subclass.");
+}
+
+#[test]
+fn test_stack_overflow() {
+    // Runaway recursion is an error, not a crash (and the depth is back to
+    // normal afterwards).  It needs the big stack, like in main.rs.
+    let run = || {
+    let src = "
+fun f(n) { return f(n + 1); }
+fun count(n) { if n == 0 { return 0; } else { return 1 + count(n - 1); } }
+class Node { init(n) { if n > 0 { this.next = Node(n - 1); } else { } } }
+";
+    let err = run_then_eval(src, "f(0)").unwrap_err();
+    assert_eq!(err.to_string(), "[line 2] Error: Stack overflow.");
+    let err = run_then_eval(src, "Node(5000)").unwrap_err();
+    assert_eq!(err.to_string(), "[line 4] Error: Stack overflow.");
+    assert_eq!(run_then_eval(src, "count(990)"), Ok(LNumber(990.0)));
+    };
+    std::thread::Builder::new().stack_size(STACK_SIZE).spawn(run).unwrap().join().unwrap();
 }
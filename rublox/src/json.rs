@@ -0,0 +1,373 @@
+// json.rs
+//
+// Just enough JSON to save syntax trees and load them back (see dump.rs).
+// There aren't any crates in this project, so it's all done by hand.
+
+use std::fmt;
+
+// Object members are kept in a Vec (not a HashMap) so that they come back
+// out in the order that they went in.  That keeps the output stable.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Json {
+    JNull,
+    JBool(bool),
+    JNumber(f64),
+    JString(String),
+    JArray(Vec<Json>),
+    JObject(Vec<(String, Json)>),
+}
+
+use Json::*;
+
+impl Json {
+    // Look up a member of an object
+    pub fn get(&self, key : &str) -> Option<&Json> {
+    match self {
+        JObject(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+        _ => None,
+    }
+    }
+
+    // Read a JSON document.  It has to be a single value (with nothing else
+    // after it, except whitespace).
+    pub fn parse(text : &str) -> Result<Json, JsonError> {
+    let mut parser = JsonParser { text, index : 0 };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.index < text.len() {
+        return Err(parser.error("Unexpected text after the end of the document."));
+    }
+    Ok(value)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct JsonError {
+    pub message : String,
+}
+
+impl JsonError {
+    pub fn new(message : &str) -> JsonError {
+    JsonError { message : message.to_string() }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Error: {}", self.message)
+    }
+}
+
+// Discussion: Output is spread over lines with two spaces of indentation
+// per level, which is what most JSON tools do.  An array of nothing but
+// numbers and the like stays on one line, since splitting up [1, 2, 3]
+// doesn't help anybody read it.
+//
+// JSON has no way to write infinity or NaN.  Like JavaScript's
+// JSON.stringify(), they come out as null.  Anything that needs to keep
+// them has to say how in its own schema (see dump.rs).
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut out = String::new();
+    write_json(&mut out, self, 0);
+    f.write_str(&out)
+    }
+}
+
+fn write_json(out : &mut String, value : &Json, depth : usize) {
+    let indent = |out : &mut String, depth : usize| out.push_str(&"  ".repeat(depth));
+    match value {
+    JNull => out.push_str("null"),
+    JBool(value) => out.push_str(if *value { "true" } else { "false" }),
+    JNumber(value) if !value.is_finite() => out.push_str("null"),
+    JNumber(value) => out.push_str(&value.to_string()),
+    JString(value) => write_string(out, value),
+    JArray(items) if items.is_empty() => out.push_str("[]"),
+    JArray(items) if items.iter().all(|item| !matches!(item, JArray(_) | JObject(_))) => {
+        let items : Vec<String> = items.iter().map(|item| item.to_string()).collect();
+        out.push_str(&format!("[{}]", items.join(", ")));
+    },
+    JArray(items) => {
+        out.push_str("[\n");
+        for (n, item) in items.iter().enumerate() {
+        indent(out, depth + 1);
+        write_json(out, item, depth + 1);
+        out.push_str(if n + 1 < items.len() { ",\n" } else { "\n" });
+        }
+        indent(out, depth);
+        out.push(']');
+    },
+    JObject(members) if members.is_empty() => out.push_str("{}"),
+    JObject(members) => {
+        out.push_str("{\n");
+        for (n, (name, value)) in members.iter().enumerate() {
+        indent(out, depth + 1);
+        write_string(out, name);
+        out.push_str(": ");
+        write_json(out, value, depth + 1);
+        out.push_str(if n + 1 < members.len() { ",\n" } else { "\n" });
+        }
+        indent(out, depth);
+        out.push('}');
+    },
+    }
+}
+
+fn write_string(out : &mut String, value : &str) {
+    out.push('"');
+    for ch in value.chars() {
+    match ch {
+        '"' => out.push_str("\\\""),
+        '\\' => out.push_str("\\\\"),
+        '\n' => out.push_str("\\n"),
+        '\r' => out.push_str("\\r"),
+        '\t' => out.push_str("\\t"),
+        ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+        ch => out.push(ch),
+    }
+    }
+    out.push('"');
+}
+
+struct JsonParser<'a> {
+    text : &'a str,
+    index : usize,      // Byte offset of the next character
+}
+
+impl JsonParser<'_> {
+    fn peek(&self) -> Option<char> {
+    self.text[self.index..].chars().next()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+    let ch = self.peek()?;
+    self.index += ch.len_utf8();
+    Some(ch)
+    }
+
+    fn accept(&mut self, ch : char) -> bool {
+    if self.peek() == Some(ch) {
+        self.index += ch.len_utf8();
+        true
+    } else {
+        false
+    }
+    }
+
+    fn expect(&mut self, ch : char) -> Result<(), JsonError> {
+    if self.accept(ch) {
+        Ok(())
+    } else {
+        Err(self.error(&format!("Expected '{ch}'.")))
+    }
+    }
+
+    fn skip_whitespace(&mut self) {
+    while self.peek().is_some_and(|ch| matches!(ch, ' ' | '\t' | '\n' | '\r')) {
+        self.advance();
+    }
+    }
+
+    // An error at the current position
+    fn error(&self, message : &str) -> JsonError {
+    let before = &self.text[..self.index];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&ch| ch != '\n').count() + 1;
+    JsonError { message : format!("{message} (line {line}, column {column})") }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, JsonError> {
+    self.skip_whitespace();
+    match self.peek() {
+        Some('{') => self.parse_object(),
+        Some('[') => self.parse_array(),
+        Some('"') => Ok(JString(self.parse_string()?)),
+        Some('-' | '0'..='9') => self.parse_number(),
+        Some(_) if self.accept_word("null") => Ok(JNull),
+        Some(_) if self.accept_word("true") => Ok(JBool(true)),
+        Some(_) if self.accept_word("false") => Ok(JBool(false)),
+        Some(_) => Err(self.error("Expected a value.")),
+        None => Err(self.error("Unexpected end of the document.")),
+    }
+    }
+
+    fn accept_word(&mut self, word : &str) -> bool {
+    if self.text[self.index..].starts_with(word) {
+        self.index += word.len();
+        true
+    } else {
+        false
+    }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, JsonError> {
+    self.expect('{')?;
+    let mut members = Vec::new();
+    self.skip_whitespace();
+    if self.accept('}') {
+        return Ok(JObject(members));
+    }
+    loop {
+        self.skip_whitespace();
+        if self.peek() != Some('"') {
+        return Err(self.error("Expected a member name."));
+        }
+        let name = self.parse_string()?;
+        self.skip_whitespace();
+        self.expect(':')?;
+        let value = self.parse_value()?;
+        members.push((name, value));
+        self.skip_whitespace();
+        if !self.accept(',') {
+        break;
+        }
+    }
+    self.expect('}')?;
+    Ok(JObject(members))
+    }
+
+    fn parse_array(&mut self) -> Result<Json, JsonError> {
+    self.expect('[')?;
+    let mut items = Vec::new();
+    self.skip_whitespace();
+    if self.accept(']') {
+        return Ok(JArray(items));
+    }
+    loop {
+        items.push(self.parse_value()?);
+        self.skip_whitespace();
+        if !self.accept(',') {
+        break;
+        }
+    }
+    self.expect(']')?;
+    Ok(JArray(items))
+    }
+
+    // -? digits ( . digits )? ( [eE] [+-]? digits )?
+    fn parse_number(&mut self) -> Result<Json, JsonError> {
+    let start = self.index;
+    let digits = |parser : &mut Self| {
+        let first = parser.index;
+        while parser.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+        parser.advance();
+        }
+        parser.index > first
+    };
+    self.accept('-');
+    let mut wellformed = digits(self);
+    if self.accept('.') {
+        wellformed &= digits(self);
+    }
+    if self.accept('e') || self.accept('E') {
+        if !self.accept('+') {
+        self.accept('-');
+        }
+        wellformed &= digits(self);
+    }
+    match self.text[start..self.index].parse::<f64>() {
+        Ok(value) if wellformed && value.is_finite() => Ok(JNumber(value)),
+        Ok(_) if wellformed => Err(self.error("Number is out of range.")),
+        _ => Err(self.error("Malformed number.")),
+    }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+    self.expect('"')?;
+    let mut value = String::new();
+    loop {
+        match self.advance() {
+        Some('"') => break,
+        Some('\\') => {
+            let ch = match self.advance() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => self.parse_unicode_escape()?,
+            _ => return Err(self.error("Invalid escape sequence.")),
+            };
+            value.push(ch);
+        },
+        Some(ch) if (ch as u32) < 0x20 => return Err(self.error("Control character in string.")),
+        Some(ch) => value.push(ch),
+        None => return Err(self.error("Unterminated string.")),
+        }
+    }
+    Ok(value)
+    }
+
+    // The XXXX of \uXXXX.  Characters outside of the basic plane are
+    // written as a pair of surrogates: \uD83D\uDE00.
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+    let high = self.parse_hex4()?;
+    let code = if (0xD800..0xDC00).contains(&high) {
+        if !(self.accept('\\') && self.accept('u')) {
+        return Err(self.error("Unpaired surrogate in string."));
+        }
+        let low = self.parse_hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+        return Err(self.error("Unpaired surrogate in string."));
+        }
+        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+    } else {
+        high
+    };
+    char::from_u32(code).ok_or_else(|| self.error("Invalid unicode escape sequence."))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+    let digits = self.text.get(self.index..self.index + 4).filter(|digits| digits.chars().all(|ch| ch.is_ascii_hexdigit()));
+    let Some(digits) = digits else {
+        return Err(self.error("Invalid unicode escape sequence."));
+    };
+    self.index += 4;
+    Ok(u32::from_str_radix(digits, 16).expect("checked hex digits"))
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Json::parse("null"), Ok(JNull));
+    assert_eq!(Json::parse(" [true, false, -1.5e2, 0, \"a\"] "),
+               Ok(JArray(vec![JBool(true), JBool(false), JNumber(-150.0), JNumber(0.0), JString(String::from("a"))])));
+    assert_eq!(Json::parse("{\"a\": {}, \"b\": []}"),
+               Ok(JObject(vec![(String::from("a"), JObject(vec![])), (String::from("b"), JArray(vec![]))])));
+    assert_eq!(Json::parse(r#""\"\\\/\b\f\n\r\t\u0041\u00e9\ud83d\ude00""#),
+               Ok(JString(String::from("\"\\/\u{8}\u{c}\n\r\tAé😀"))));
+    let value = Json::parse("{\"x\": 1, \"y\": 2}").unwrap();
+    assert_eq!(value.get("y"), Some(&JNumber(2.0)));
+    assert_eq!(value.get("z"), None);
+}
+
+#[test]
+fn test_parse_errors() {
+    let message = |text : &str| Json::parse(text).unwrap_err().to_string();
+    assert_eq!(message(""), "Error: Unexpected end of the document. (line 1, column 1)");
+    assert_eq!(message("[1, 2"), "Error: Expected ']'. (line 1, column 6)");
+    assert_eq!(message("{\n  \"a\" 1}"), "Error: Expected ':'. (line 2, column 7)");
+    assert_eq!(message("{1: 2}"), "Error: Expected a member name. (line 1, column 2)");
+    assert_eq!(message("[1.]"), "Error: Malformed number. (line 1, column 4)");
+    assert_eq!(message("-"), "Error: Malformed number. (line 1, column 2)");
+    assert_eq!(message("[1e999]"), "Error: Number is out of range. (line 1, column 7)");
+    assert_eq!(message("\"abc"), "Error: Unterminated string. (line 1, column 5)");
+    assert_eq!(message("\"\\x\""), "Error: Invalid escape sequence. (line 1, column 4)");
+    assert_eq!(message("\"\\ud83d\""), "Error: Unpaired surrogate in string. (line 1, column 8)");
+    assert_eq!(message("\"a\nb\""), "Error: Control character in string. (line 2, column 1)");
+    assert_eq!(message("nope"), "Error: Expected a value. (line 1, column 1)");
+    assert_eq!(message("1 2"), "Error: Unexpected text after the end of the document. (line 1, column 3)");
+}
+
+#[test]
+fn test_display() {
+    let value = Json::parse("{\"a\": [1, 2.5, \"x\\ny\", null], \"b\": [{\"c\": true}], \"d\": {}, \"e\": []}").unwrap();
+    let text = value.to_string();
+    assert_eq!(text, "{\n  \"a\": [1, 2.5, \"x\\ny\", null],\n  \"b\": [\n    {\n      \"c\": true\n    }\n  ],\n  \"d\": {},\n  \"e\": []\n}");
+    assert_eq!(Json::parse(&text), Ok(value));
+    assert_eq!(JString(String::from("\u{1}")).to_string(), "\"\\u0001\"");
+    assert_eq!(JArray(vec![JNumber(f64::NAN), JNumber(f64::INFINITY)]).to_string(), "[null, null]");
+}
@@ -5,6 +5,10 @@ pub mod tokenize;
 pub mod interp;
 pub mod parse;
 pub mod ast;
+pub mod environ;
+pub mod format;
+pub mod json;
+pub mod dump;
 
 // Type definitions here?
 pub type Filename = String;       // () = "Unit" (kind of like "None" in Python)
@@ -23,7 +27,7 @@ pub type Source = String;
 // Note: PartialEq is so that token types can be compared with ==
 //       Debug is so that token types can be printed.
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum TokenType {
     // Symbols
     LPAREN, RPAREN, LBRACE, RBRACE,   // ( ) { }
@@ -36,15 +40,49 @@ pub enum TokenType {
     IDENTIFIER,
     STRING,
     NUMBER,
+    INTERPOLATION,    // Part of a string up to a ${
+    STRINGMID,        // Part of a string from a } to the next ${
+    STRINGEND,        // Rest of a string after the last }
 
     // Keywords
-    AND, CLASS, ELSE, FALSE, FUN, FOR, IF, NIL, OR,
+    AND, BREAK, CLASS, CONTINUE, ELSE, FALSE, FUN, FOR, IF, NIL, OR,
     PRINT, RETURN, SUPER, THIS, TRUE, VAR, WHILE,
     EOF,
 
     // Ignored tokens
     WHITESPACE,
-    COMMENT
+    COMMENT,
+    ERROR,            // Text the scanner rejected (kept only when lossless)
+}
+
+// A region of the source code.  start/end are byte offsets into the
+// source (end is one past the last byte).  line/column are where the
+// region starts, both counting from 1.  Column counts characters, not bytes.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct Span {
+    pub start : usize,
+    pub end : usize,
+    pub line : usize,
+    pub column : usize,
+}
+
+impl Span {
+    pub fn new(start : usize, end : usize, line : usize, column : usize) -> Span {
+    Span { start, end, line, column }
+    }
+    // Span covering everything from the start of self to the end of other
+    pub fn to(self, other : Span) -> Span {
+    Span { end : other.end, ..self }
+    }
+}
+
+// The value of a literal token, as worked out by the scanner.  For example,
+// the string token "a\tb" (with a backslash and quotes) has the value a<tab>b
+// and the number token 0x1_00 has the value 256.
+#[derive(PartialEq, Debug)]
+pub enum Literal {
+    LitString(String),
+    LitNumber(f64),
 }
 
 #[derive(PartialEq, Debug)]
@@ -53,15 +91,43 @@ pub struct Token {
     lexeme  : String,
     // Discussion: CI (Crafting Interpreters) describes an extra field
     // here called "literal" that is set to Object (meaning any Java object).
-    // I am not aware of anything comparable to that in Rust. So, I am
-    // leaving it off completely.   A token has a type, a value, and a line.
-    // However: see https://github.com/dabeaz-course/rust_2024_06/discussions/5
-    line : i32,
+    // I am not aware of anything comparable to that in Rust.  However, an
+    // enum of the possible kinds of literal values does the same job.
+    // See https://github.com/dabeaz-course/rust_2024_06/discussions/5
+    literal : Option<Literal>,
+    span : Span,
+    // Whitespace and comments around the token.  These are only filled in
+    // by a lossless scanner (see tokenize.rs).  Leading trivia is whatever
+    // comes before the token.  Trailing trivia runs to the end of its line.
+    leading : Tokens,
+    trailing : Tokens,
 }
 
 impl Token {
-    pub fn new(toktype : TokenType, lexeme : &str, line : i32) -> Token {
-    Token { toktype, lexeme : String::from(lexeme), line }
+    pub fn new(toktype : TokenType, lexeme : &str, span : Span) -> Token {
+    Token { toktype, lexeme : String::from(lexeme), literal : None, span, leading : Vec::new(), trailing : Vec::new() }
+    }
+    pub fn with_literal(toktype : TokenType, lexeme : &str, literal : Literal, span : Span) -> Token {
+    Token { literal : Some(literal), ..Token::new(toktype, lexeme, span) }
+    }
+    pub fn toktype(&self) -> TokenType {
+    self.toktype
+    }
+    pub fn lexeme(&self) -> &str {
+    &self.lexeme
+    }
+    pub fn span(&self) -> Span {
+    self.span
+    }
+    pub fn leading(&self) -> &[Token] {
+    &self.leading
+    }
+    pub fn trailing(&self) -> &[Token] {
+    &self.trailing
+    }
+    // Is this whitespace, a comment, or rejected text?
+    pub fn is_trivia(&self) -> bool {
+    matches!(self.toktype, TokenType::WHITESPACE | TokenType::COMMENT | TokenType::ERROR)
     }
 }
 
@@ -1,28 +1,192 @@
 use rublox::reader::*;
-use rublox::tokenize::*;
 use rublox::parse::*;
 use rublox::interp::*;
+use rublox::format::format_source;
+use rublox::dump::{load_json, to_json, to_sexp};
+use rublox::tokenize::Scanner;
 use rublox::Filename;
 
 fn main() {
+    // Lox calls nest on the Rust stack, so everything runs on a thread with
+    // plenty of it (see MAX_CALL_DEPTH in interp.rs)
+    let thread = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(run).unwrap_or_else(|err| {
+    eprintln!("Can't start the interpreter: {err}");
+    std::process::exit(71);         // EX_OSERR
+    });
+    if thread.join().is_err() {
+    std::process::exit(101);        // Same as a panic on the main thread
+    }
+}
+
+fn run() {
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+    Some("fmt") => format_files(&args[1..]),
+    Some("ast") => dump_ast(&args[1..]),
+    _ => { },
+    }
     println!("Hello, Lox!");
     // Interpreter is going to involve some different steps.  Right now,
     // this is a tremendous amount of "wishful thinking" on my part.
     // But, at a very high level, this is how an interpreter is going to
     // be put together and how the flow of data will work.
-    let filename = get_filename_from_args();
+    let (filename, dialect) = get_args(&args);
     let src = read_source(&filename);
-    let tokens = tokenize(&src);
-    let ast = parse(tokens);
-    interpret(&ast);
+    // A program can also come as a syntax tree in JSON (see dump.rs)
+    let ast = if filename.ends_with(".json") {
+    load_json(&src).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(65);
+    })
+    } else {
+    match parse_with_dialect(&src, dialect) {
+        Ok(ast) => ast,
+        Err(errors) => {
+        // Lexical and syntax errors exit with status 65 (EX_DATAERR) like the reference Lox
+        for err in errors.iter() {
+            eprintln!("{err}");
+        }
+        std::process::exit(65);
+        }
+    }
+    };
+    if let Err(err) = interpret(&ast) {
+    // Runtime errors exit with status 70 (EX_SOFTWARE) like the reference Lox
+    eprintln!("{err}");
+    std::process::exit(70);
+    }
 }
 
 // use std::env;
 
-// Read the input filename from the command line arguments
-fn get_filename_from_args() -> Filename {
-    let filename = std::env::args().nth(1).expect("Missing filename");
+// Read the input filename (and options) from the command line arguments.
+//
+//     rublox [--dialect rublox|standard] filename
+fn get_args(args : &[String]) -> (Filename, Dialect) {
     println!("Getting filename from command line");
+    let mut filename = None;
+    let mut dialect = Dialect::default();
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+    if arg == "--dialect" {
+        let name = args.next().unwrap_or_else(|| usage("Missing dialect name"));
+        dialect = name.parse().unwrap_or_else(|err : String| usage(&err));
+    } else if filename.is_none() {
+        filename = Some(arg);
+    } else {
+        usage(&format!("Unexpected argument '{arg}'"));
+    }
+    }
+    let filename = filename.unwrap_or_else(|| usage("Missing filename"));
     println!("filename={filename}");
-    return filename;
+    (filename, dialect)
+}
+
+// Format files in place.  With --check, nothing gets changed.  Instead, the
+// files that aren't formatted are listed and the exit status is 1 (handy
+// for CI).
+//
+//     rublox fmt [--check] [--dialect rublox|standard] filename...
+fn format_files(args : &[String]) -> ! {
+    let mut check = false;
+    let mut dialect = Dialect::default();
+    let mut filenames = Vec::new();
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+    match arg.as_str() {
+        "--check" => check = true,
+        "--dialect" => {
+        let name = args.next().unwrap_or_else(|| usage("Missing dialect name"));
+        dialect = name.parse().unwrap_or_else(|err : String| usage(&err));
+        },
+        _ => filenames.push(arg),
+    }
+    }
+    if filenames.is_empty() {
+    usage("Missing filename");
+    }
+    let mut status = 0;
+    for filename in filenames.iter() {
+    let src = read_file(filename);
+    match format_source(&src, dialect) {
+        Ok(formatted) if formatted == src => { },
+        Ok(_) if check => {
+        println!("{filename} is not formatted");
+        status = status.max(1);
+        },
+        Ok(formatted) => {
+        std::fs::write(filename, formatted).unwrap_or_else(|err| {
+            eprintln!("{filename}: {err}");
+            std::process::exit(74);    // EX_IOERR
+        });
+        },
+        Err(errors) => {
+        for err in errors.iter() {
+            eprintln!("{filename}: {err}");
+        }
+        status = 65;
+        },
+    }
+    }
+    std::process::exit(status);
+}
+
+// Show what the parser makes of a file, as S-expressions (the default) or
+// as JSON that can be loaded back in.
+//
+//     rublox ast [--format json|sexp] [--dialect rublox|standard] filename
+fn dump_ast(args : &[String]) -> ! {
+    let mut json = false;
+    let mut dialect = Dialect::default();
+    let mut filename = None;
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+    match arg.as_str() {
+        "--format" => {
+        json = match args.next().as_deref() {
+            Some("json") => true,
+            Some("sexp") => false,
+            Some(other) => usage(&format!("Unknown format '{other}'")),
+            None => usage("Missing format name"),
+        };
+        },
+        "--dialect" => {
+        let name = args.next().unwrap_or_else(|| usage("Missing dialect name"));
+        dialect = name.parse().unwrap_or_else(|err : String| usage(&err));
+        },
+        _ if filename.is_none() => filename = Some(arg),
+        _ => usage(&format!("Unexpected argument '{arg}'")),
+    }
+    }
+    let filename = filename.unwrap_or_else(|| usage("Missing filename"));
+    let src = read_file(&filename);
+    let ast = source_dialect(&src, dialect).and_then(|dialect| parse_tokens(Scanner::new(&src), dialect));
+    match ast {
+    Ok(ast) if json => println!("{}", to_json(&ast)),
+    Ok(ast) => print!("{}", to_sexp(&ast)),
+    Err(errors) => {
+        for err in errors.iter() {
+        eprintln!("{err}");
+        }
+        std::process::exit(65);
+    },
+    }
+    std::process::exit(0);
+}
+
+// Read a whole file (quietly, unlike read_source)
+fn read_file(filename : &str) -> String {
+    std::fs::read_to_string(filename).unwrap_or_else(|err| {
+    eprintln!("{filename}: {err}");
+    std::process::exit(66);        // EX_NOINPUT
+    })
+}
+
+fn usage(message : &str) -> ! {
+    eprintln!("{message}");
+    eprintln!("Usage: rublox [--dialect rublox|standard] filename");
+    eprintln!("       rublox fmt [--check] [--dialect rublox|standard] filename...");
+    eprintln!("       rublox ast [--format json|sexp] [--dialect rublox|standard] filename");
+    // 64 is EX_USAGE
+    std::process::exit(64);
 }
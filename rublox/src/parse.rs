@@ -2,80 +2,293 @@
 //
 // Parse Lox code
 
-use crate::{Tokens, TokenType, Token, AST};
-use crate::ast::{Expression,Statement,Statements};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::{Literal, Span, TokenType, Token, AST};
+use crate::ast::{format_expression, Class, Expr, Expression, Function, Spanned, Stmt, Statements};
 use crate::ast::Expression::*;
 use crate::ast::Statement::*;
 use crate::ast::Op::*;
 use crate::TokenType::*;
-use crate::tokenize::tokenize;
+use crate::tokenize::{LexError, Scanner};
+
+// Discussion: Dialects.  Rublox writes conditions without parentheses
+// (if x > 3 { ... }) but the book, and every Lox program out there, writes
+// them with parentheses (if (x > 3) stmt).  The two can't be mixed.  In
+// Rublox, "if (x) -1;" is the condition (x) - 1 followed by a stray ';'.
+// So the parser has to be told which one it's reading.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Dialect {
+    #[default]
+    Rublox,         // if test { ... }
+    Standard,       // if (test) statement
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+    fn from_str(name : &str) -> Result<Dialect, String> {
+    match name {
+        "rublox" => Ok(Dialect::Rublox),
+        "standard" => Ok(Dialect::Standard),
+        _ => Err(format!("Unknown dialect '{name}'.")),
+    }
+    }
+}
+
+// A file can pick its own dialect with a comment on its first line:
+//
+//     // lox-dialect: standard
+//
+// Returns the name given (if there is one).
+pub fn dialect_pragma(src : &str) -> Option<&str> {
+    let line = src.lines().next()?.trim();
+    let name = line.strip_prefix("//")?.trim_start().strip_prefix("lox-dialect:")?;
+    Some(name.trim())
+}
+
+// Parse a whole program.  If there were any syntax errors, all of them are
+// returned (not just the first one).
+pub fn parse(src : &str) -> Result<AST, Vec<SyntaxError>> {
+    parse_with_dialect(src, Dialect::default())
+}
 
-pub fn parse(tokens : Tokens) -> AST {
+// Parse a program in the given dialect, unless the file says otherwise
+// with a pragma.  The pragma wins since it's the file that knows how it
+// was written.
+pub fn parse_with_dialect(src : &str, dialect : Dialect) -> Result<AST, Vec<SyntaxError>> {
     println!("Parsing Lox");
-    let mut parser = Parser::new(tokens);
-    parser.parse_statements().expect("syntax error")
+    let dialect = source_dialect(src, dialect)?;
+    parse_tokens(Scanner::new(src), dialect)
+}
+
+// The dialect that a file is written in: the one named by its pragma or,
+// if it doesn't have one, the given default
+pub fn source_dialect(src : &str, default : Dialect) -> Result<Dialect, Vec<SyntaxError>> {
+    match dialect_pragma(src).map(Dialect::from_str) {
+    None => Ok(default),
+    Some(Ok(dialect)) => Ok(dialect),
+    Some(Err(message)) => {
+        let line = src.lines().next().unwrap_or_default();
+        Err(vec![SyntaxError::Parse(ParseError {
+        span : Span::new(0, line.len(), 1, 1),
+        found : COMMENT,
+        lexeme : line.trim().to_string(),
+        expected : Vec::new(),
+        message,
+        })])
+    }
+    }
 }
 
-pub fn parse_expression_string(src : &str) -> Expression {
-    let s = String::from(src);
-    let tokens = tokenize(&s);
+// Parse a program from any stream of tokens
+pub fn parse_tokens<I>(tokens : I, dialect : Dialect) -> Result<AST, Vec<SyntaxError>>
+    where I : Iterator<Item = Result<Token, LexError>> {
     let mut parser = Parser::new(tokens);
+    parser.dialect = dialect;
+    let statements = parser.parse_program();
+    if parser.errors.is_empty() {
+    Ok(statements)
+    } else {
+    Err(parser.errors)
+    }
+}
+
+pub fn parse_expression_string(src : &str) -> Expr {
+    let mut parser = Parser::new(Scanner::new(src));
     parser.parse_expression().expect("failed")
 }
 
-pub fn parse_statement_string(src : &str) -> Statement {
-    let s = String::from(src);
-    let tokens = tokenize(&s);
-    let mut parser = Parser::new(tokens);
+pub fn parse_statement_string(src : &str) -> Stmt {
+    let mut parser = Parser::new(Scanner::new(src));
     parser.parse_statement().expect("failed")
 }
 
 
 // Discussion:  The Lox grammar for expressions is as follows. Tokens are ALLCAPS.
 //
-//  expression -> literal
+//  expression -> assign
+//             |  literal
 //             |  unary
 //             |  binary
+//             |  logical
+//             |  call
+//             |  get
+//             |  super
 //             |  grouping ;
 //
-// literal -> NUMBER | STRING | TRUE | FALSE | NIL ;
+// literal -> NUMBER | STRING | TRUE | FALSE | NIL | interpolation ;
+// interpolation -> INTERPOLATION expression ( STRINGMID expression )* STRINGEND ;
 // grouping -> LPAREN expression RPAREN ;
 // unary -> ( MINUS | BANG) expression ;
 // binary -> expression operator expression ;
 // operator -> PLUS | MINUS | STAR | SLASH | EQ | NE | LT | LE | GT | GE
+// logical -> expression ( AND | OR ) expression ;
+// call -> expression LPAREN ( expression ( COMMA expression )* )? RPAREN ;
+// get -> expression DOT IDENTIFIER ;
+// super -> SUPER DOT IDENTIFIER ;
+// assign -> ( call DOT )? IDENTIFIER ASSIGN expression ;
+//
+// Assignment is the loosest of all and groups to the right, so a = b = 0
+// means a = (b = 0).  Its value is the value assigned.
+// "and" binds tighter than "or" and both are looser than everything else.
+// They aren't lumped in with binary because they don't always evaluate
+// their right-hand side.
 //
 // One way to approach a parser is to write a separate function for each left-hand-side.
 // Each function needs to return something from the AST and possibly an error (if parse
 // error).
 //
 // Strategy for parsing:  You try to work left-to-right over input tokens, matching
-// them in order.
+// them in order.  Tokens are pulled from the scanner only as they're needed
+// and sit in a small lookahead buffer until they get consumed.  So, the whole
+// program never has to be turned into a list of tokens up front.
+//
+// Every node that gets built is given the span of the tokens it was made from.
+// The usual pattern is to remember the span of the first token of a construct,
+// parse the rest, and then stretch that span out to the last token consumed
+// (see finish() below).
+
+// Discussion: Error handling.  A syntax error shouldn't stop the parser dead
+// in its tracks.  Instead, the error gets recorded and the parser skips ahead
+// to a spot where it's likely that a new statement begins (see synchronize()).
+// That way, a single run of the parser can report every error in a file.
+//
+// To give good messages, the parser keeps track of every token type that it
+// tried to match (with check()) at the current position.  When it gets stuck,
+// that's the set of tokens that would have been acceptable.
+
+#[derive(PartialEq, Debug)]
+pub struct ParseError {
+    pub span : Span,                  // Where the error occurred
+    pub found : TokenType,            // The token that caused the problem
+    pub lexeme : String,              // ... and its text
+    pub expected : Vec<TokenType>,    // Token types that would have been valid
+    pub message : String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.found == EOF {
+        write!(f, "[line {}] Error at end: {}", self.span.line, self.message)
+    } else {
+        write!(f, "[line {}] Error at '{}': {}", self.span.line, self.lexeme, self.message)
+    }
+    }
+}
+
+// Since tokens are scanned as the parser goes, bad tokens from the scanner
+// turn up in the middle of parsing.  They're collected up along with the
+// parser's own errors (in the order they were found).
+#[derive(PartialEq, Debug)]
+pub enum SyntaxError {
+    Lex(LexError),
+    Parse(ParseError),
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+        SyntaxError::Lex(err) => err.fmt(f),
+        SyntaxError::Parse(err) => err.fmt(f),
+    }
+    }
+}
 
-struct Parser {
-    tokens : Tokens,    // From scanner
-    current : usize     // Current position of parse (work left-to-right)
+struct Parser<I> {
+    tokens : I,                     // From scanner
+    lookahead : VecDeque<Token>,    // Tokens pulled from the scanner, but not consumed yet
+    previous : Option<Token>,       // Last token consumed
+    expected : Vec<TokenType>,      // Token types tried at the current position
+    errors : Vec<SyntaxError>,      // All syntax errors found so far
+    in_function : bool,             // Inside a function body? (return is allowed)
+    in_loop : bool,                 // Inside a loop body? (break and continue are allowed)
+    in_initializer : bool,          // Inside an init() method? (return can't have a value)
+    in_class : bool,                // Inside a class? (this is allowed)
+    in_subclass : bool,             // Inside a class with a superclass? (super is allowed)
+    dialect : Dialect,              // How if/while conditions are written
 }
 
-impl Parser {
-    fn new(tokens: Tokens) -> Parser {
-    Parser { tokens, current: 0 }
+impl<I : Iterator<Item = Result<Token, LexError>>> Parser<I> {
+    fn new(tokens: I) -> Parser<I> {
+    Parser { tokens, lookahead: VecDeque::new(), previous: None, expected: Vec::new(), errors: Vec::new(),
+             in_function: false, in_loop: false, in_initializer: false, in_class: false, in_subclass: false,
+             dialect: Dialect::default() }
     }
 
     fn previous(&self) -> &Token {
-    &self.tokens[self.current-1]
+    self.previous.as_ref().expect("no token consumed yet")
+    }
+
+    // Pull tokens from the scanner until there are n of them waiting in the
+    // lookahead buffer (or the end of input is reached).  Bad tokens get
+    // recorded as errors and skipped.
+    fn fill(&mut self, n : usize) {
+    while self.lookahead.len() < n && self.lookahead.back().is_none_or(|tok| tok.toktype != EOF) {
+        match self.tokens.next() {
+        Some(Ok(tok)) => self.lookahead.push_back(tok),
+        Some(Err(err)) => self.errors.push(SyntaxError::Lex(err)),
+        None => {
+            // The tokens ran out without an EOF.  Make one up just past the last token.
+            let span = match (self.lookahead.back(), &self.previous) {
+            (Some(tok), _) | (None, Some(tok)) => Span { start : tok.span.end, ..tok.span },
+            (None, None) => Span::new(0, 0, 1, 1),
+            };
+            self.lookahead.push_back(Token::new(EOF, "", span));
+        }
+        }
+    }
+    }
+
+    // The next token (without consuming it)
+    fn peek(&mut self) -> &Token {
+    self.fill(1);
+    &self.lookahead[0]
+    }
+
+    // Span of the next token (without consuming it)
+    fn peek_span(&mut self) -> Span {
+    self.peek().span
     }
 
-    // Check next token *without* consuming it
-    fn check(&self, tty: TokenType) -> bool {
-    (self.current < self.tokens.len() && self.tokens[self.current].toktype == tty)
-        || (self.current >= self.tokens.len() && tty == EOF)
+    // Wrap up a node with a span running from start to the last token consumed
+    fn finish<T>(&self, node : T, start : Span) -> Spanned<T> {
+    Spanned::new(node, start.to(self.previous().span))
+    }
+
+    // Type of the next token (without consuming it)
+    fn peek_type(&mut self) -> TokenType {
+    self.peek().toktype
+    }
+
+    // Check next token *without* consuming it.  Failed checks are remembered
+    // in case they're needed for an error message.
+    fn check(&mut self, tty: TokenType) -> bool {
+    if self.peek_type() == tty {
+        true
+    } else {
+        if !self.expected.contains(&tty) {
+        self.expected.push(tty);
+        }
+        false
+    }
+    }
+
+    // Move on to the next token.  The EOF token stays put once reached.
+    fn advance(&mut self) {
+    if self.peek_type() != EOF {
+        self.previous = self.lookahead.pop_front();
+    }
+    self.expected.clear();
     }
 
     // If next token matches return true and advance.
     // This is called "match" in Crafting Interpreters
     fn accept(&mut self, tty: TokenType) -> bool {
     if self.check(tty) {
-        self.current += 1;
+        self.advance();
         true
     } else {
         false
@@ -83,20 +296,97 @@ impl Parser {
     }
 
     // Require the next token to exactly match an expected type or a syntax error
-    fn consume(&mut self, tty: TokenType, message: &str) -> Result<&Token, String> {
-    if self.tokens[self.current].toktype == tty {
-        self.current += 1;
-        Ok(&self.tokens[self.current-1])
+    fn consume(&mut self, tty: TokenType, message: &str) -> Result<&Token, ParseError> {
+    if self.accept(tty) {
+        Ok(self.previous())
     } else {
-        Err(String::from(message))
+        Err(self.error(message))
+    }
     }
+
+    // Make a syntax error for the next token
+    fn error(&mut self, message: &str) -> ParseError {
+    let expected = self.expected.clone();
+    let tok = self.peek();
+    ParseError {
+        span : tok.span,
+        found : tok.toktype,
+        lexeme : tok.lexeme.clone(),
+        expected,
+        message : String::from(message),
+    }
+    }
+
+    // Record a syntax error that doesn't stop the parse (the code is wrong,
+    // but it's clear enough how to carry on)
+    fn report(&mut self, err : ParseError) {
+    self.errors.push(SyntaxError::Parse(err));
+    }
+
+    // After a syntax error, skip tokens until reaching a place where it looks
+    // like a new statement might start.  That's just after a semicolon, or
+    // before a keyword that starts a statement or a '}' that ends a block.
+    fn synchronize(&mut self) {
+    loop {
+        match self.peek_type() {
+        EOF | RBRACE | VAR | IF | WHILE | PRINT | FOR | FUN | CLASS | RETURN | BREAK | CONTINUE => break,
+        SEMICOLON => {
+            self.advance();
+            break;
+        },
+        _ => self.advance()
+        }
+    }
+    self.expected.clear();
     }
 
     // Expression Parsing
-    fn parse_expression(&mut self) -> Result<Expression, String> {
-    self.parse_equality()
+    fn parse_expression(&mut self) -> Result<Expr, ParseError> {
+    self.parse_assignment()
+    }
+    // Discussion: There's no telling that an expression is the target of
+    // an assignment until the '=' turns up after it.  So, the target gets
+    // parsed like any other expression and checked afterwards.  Only a
+    // variable or a property can be assigned.  A bad target is reported,
+    // but it doesn't throw the parser off, so there's no need to skip ahead.
+    fn parse_assignment(&mut self) -> Result<Expr, ParseError> {
+    let found = self.peek_type();
+    let target = self.parse_or()?;
+    if !self.accept(ASSIGN) {
+        return Ok(target);
+    }
+    let start = target.span;
+    let value = self.parse_assignment()?;
+    if !matches!(target.node, EName(_) | EGet(..)) {
+        self.report(ParseError {
+        span : target.span,
+        found,
+        lexeme : format_expression(&target.node),
+        expected : Vec::new(),
+        message : String::from("Invalid assignment target."),
+        });
+    }
+    Ok(self.finish(EAssign(Box::new(target), Box::new(value)), start))
+    }
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+    let mut expr = self.parse_and()?;
+    while self.accept(OR) {
+        let start = expr.span;
+        let right = self.parse_and()?;
+        expr = self.finish(ELogical(OpOr, Box::new(expr), Box::new(right)), start);
+    }
+    Ok(expr)
+    }
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+    let mut expr = self.parse_equality()?;
+    while self.accept(AND) {
+        let start = expr.span;
+        let right = self.parse_equality()?;
+        expr = self.finish(ELogical(OpAnd, Box::new(expr), Box::new(right)), start);
+    }
+    Ok(expr)
     }
-    fn parse_equality(&mut self) -> Result<Expression, String> {
+    fn parse_equality(&mut self) -> Result<Expr, ParseError> {
     let mut expr = self.parse_comparison()?;
     while self.accept(EQ) || self.accept(NE) {
         let op = match self.previous().toktype {
@@ -104,12 +394,14 @@ impl Parser {
         NE => OpNe,
         _ => panic!("Should not be here")
         };
-        expr = EBinary(op, Box::new(expr), Box::new(self.parse_comparison()?));
+        let start = expr.span;
+        let right = self.parse_comparison()?;
+        expr = self.finish(EBinary(op, Box::new(expr), Box::new(right)), start);
     }
     Ok(expr)
     }
 
-    fn parse_comparison(&mut self) -> Result<Expression, String> {
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
     let mut expr = self.parse_term()?;
     while self.accept(LT) || self.accept(LE) || self.accept(GT) || self.accept(GE) {
         let op = match self.previous().toktype {
@@ -119,11 +411,13 @@ impl Parser {
         GE => OpGe,
         _ => panic!("Should not be here")
         };
-        expr = EBinary(op, Box::new(expr), Box::new(self.parse_term()?));
+        let start = expr.span;
+        let right = self.parse_term()?;
+        expr = self.finish(EBinary(op, Box::new(expr), Box::new(right)), start);
     }
     Ok(expr)
     }
-    fn parse_term(&mut self) -> Result<Expression, String> {
+    fn parse_term(&mut self) -> Result<Expr, ParseError> {
     let mut expr = self.parse_factor()?;
     while self.accept(PLUS) || self.accept(MINUS) {
         let op = match self.previous().toktype {
@@ -131,11 +425,13 @@ impl Parser {
         MINUS => OpMinus,
         _ => panic!("Should not be here")
         };
-        expr = EBinary(op, Box::new(expr), Box::new(self.parse_factor()?));
+        let start = expr.span;
+        let right = self.parse_factor()?;
+        expr = self.finish(EBinary(op, Box::new(expr), Box::new(right)), start);
     }
     Ok(expr)
     }
-    fn parse_factor(&mut self) -> Result<Expression, String> {
+    fn parse_factor(&mut self) -> Result<Expr, ParseError> {
     let mut expr = self.parse_unary()?;
     while self.accept(SLASH) || self.accept(STAR) {
         let op = match self.previous().toktype {
@@ -143,128 +439,439 @@ impl Parser {
         STAR => OpMult,
         _ => panic!("Should not be here")
         };
-        expr = EBinary(op, Box::new(expr), Box::new(self.parse_unary()?));
+        let start = expr.span;
+        let right = self.parse_unary()?;
+        expr = self.finish(EBinary(op, Box::new(expr), Box::new(right)), start);
     }
     Ok(expr)
     }
-    fn parse_unary(&mut self) -> Result<Expression, String> {
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
     if self.accept(MINUS) || self.accept(BANG) {
+        let start = self.previous().span;
         let op = match self.previous().toktype {
         MINUS => OpMinus,
         BANG => OpNot,
         _ => panic!("Should not be here")
         };
         let right = self.parse_unary()?;
-        Ok(EUnary(op, Box::new(right)))
+        Ok(self.finish(EUnary(op, Box::new(right)), start))
     } else {
-        self.parse_primary()
+        self.parse_call()
+    }
     }
+    fn parse_call(&mut self) -> Result<Expr, ParseError> {
+    let mut expr = self.parse_primary()?;
+    loop {
+        let start = expr.span;
+        if self.accept(DOT) {
+        let name = self.consume(IDENTIFIER, "Expect property name after '.'.")?.lexeme.clone();
+        expr = self.finish(EGet(Box::new(expr), name), start);
+        continue;
+        } else if !self.accept(LPAREN) {
+        break;
+        }
+        let mut arguments = Vec::new();
+        if !self.check(RPAREN) {
+        loop {
+            if arguments.len() == 255 {
+            let err = self.error("Can't have more than 255 arguments.");
+            self.report(err);
+            }
+            arguments.push(self.parse_expression()?);
+            if !self.accept(COMMA) {
+            break;
+            }
+        }
+        }
+        self.consume(RPAREN, "Expect ')' after arguments.")?;
+        expr = self.finish(ECall(Box::new(expr), arguments), start);
     }
-    fn parse_primary(&mut self) -> Result<Expression, String> {
-    if self.accept(FALSE) {
-        Ok(EBoolean(false))
+    Ok(expr)
+    }
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+    let start = self.peek_span();
+    let expr = if self.accept(FALSE) {
+        EBoolean(false)
     } else if self.accept(TRUE) {
-        Ok(EBoolean(true))
+        EBoolean(true)
     } else if self.accept(NIL) {
-        Ok(ENil)
+        ENil
     } else if self.accept(NUMBER) {
-        Ok(ENumber(self.previous().lexeme.parse().expect("")))
+        match self.previous().literal {
+        Some(Literal::LitNumber(value)) => ENumber(value),
+        _ => panic!("Should not be here")
+        }
     } else if self.accept(STRING) {
-        let lexeme = &self.previous().lexeme;
-        // println!("{lexeme:?}");
-        Ok(EString(lexeme[1..lexeme.len()-1].to_string()))
+        EString(self.string_value())
+    } else if self.accept(INTERPOLATION) {
+        self.parse_interpolation()?
     } else if self.accept(LPAREN) {
         let expr = self.parse_expression()?;
         self.consume(RPAREN, "Expect ')' after expression.")?;
-        Ok(EGroup(Box::new(expr)))
+        EGroup(Box::new(expr))
     } else if self.accept(IDENTIFIER) {
-        Ok(EName(self.previous().lexeme.clone()))
+        EName(self.previous().lexeme.clone())
+    } else if self.check(THIS) {
+        if !self.in_class {
+        let err = self.error("Can't use 'this' outside of a class.");
+        self.report(err);
+        }
+        self.advance();
+        EThis
+    } else if self.check(SUPER) {
+        if !self.in_class {
+        let err = self.error("Can't use 'super' outside of a class.");
+        self.report(err);
+        } else if !self.in_subclass {
+        let err = self.error("Can't use 'super' in a class with no superclass.");
+        self.report(err);
+        }
+        self.advance();
+        self.consume(DOT, "Expect '.' after 'super'.")?;
+        ESuper(self.consume(IDENTIFIER, "Expect superclass method name.")?.lexeme.clone())
     } else {
-        Err(String::from("Expected a primary"))
+        return Err(self.error("Expect expression."))
+    };
+    Ok(self.finish(expr, start))
+    }
+
+    // A string with expressions in it like "a ${x} b ${y} c".  The scanner
+    // hands it over in pieces: INTERPOLATION("a ") x STRINGMID(" b ")
+    // y STRINGEND(" c").  The first piece has already been consumed.
+    fn parse_interpolation(&mut self) -> Result<Expression, ParseError> {
+    let mut parts = Vec::new();
+    loop {
+        let text = self.string_value();
+        let expr = self.parse_expression()?;
+        parts.push((text, expr));
+        if !self.accept(STRINGMID) {
+        self.consume(STRINGEND, "Expect '}' after interpolated expression.")?;
+        return Ok(EInterpolate(parts, self.string_value()));
+        }
+    }
+    }
+
+    // Value of the string token just consumed
+    fn string_value(&self) -> String {
+    // The scanner already figured out the value (escape codes and all)
+    match &self.previous().literal {
+        Some(Literal::LitString(value)) => value.clone(),
+        _ => panic!("Should not be here")
     }
     }
     // Statement parsing
-    fn parse_statement(&mut self) -> Result<Statement, String> {
+    fn parse_statement(&mut self) -> Result<Stmt, ParseError> {
     if self.check(PRINT) {
         self.parse_print()
     } else if self.check(IF) {
         self.parse_if()
     } else if self.check(WHILE) {
         self.parse_while()
+    } else if self.check(FOR) {
+        self.parse_for()
     } else if self.check(VAR) {
         self.parse_var()
+    } else if self.check(FUN) {
+        self.parse_function()
+    } else if self.check(CLASS) {
+        self.parse_class()
+    } else if self.check(RETURN) {
+        self.parse_return()
+    } else if self.check(BREAK) || self.check(CONTINUE) {
+        self.parse_break()
     } else if self.check(LBRACE) {
         self.parse_block()
     } else {
         self.parse_statement_expr()
     }
     }
-    fn parse_print(&mut self) -> Result<Statement, String> {
+    fn parse_print(&mut self) -> Result<Stmt, ParseError> {
+    let start = self.peek_span();
     self.consume(PRINT, "Expected 'print'")?;
     let value = self.parse_expression()?;
     self.consume(SEMICOLON, "Expect ';' after expression.")?;
-    Ok(SPrint(value))
+    Ok(self.finish(SPrint(value), start))
     }
-    fn parse_var(&mut self) -> Result<Statement, String> {
+    fn parse_var(&mut self) -> Result<Stmt, ParseError> {
     // var name [ = value ];
+    let start = self.peek_span();
     self.consume(VAR, "Expected 'var'")?;
-    self.consume(IDENTIFIER, "Expected identifier")?;
+    self.consume(IDENTIFIER, "Expect variable name.")?;
     let name = self.previous().lexeme.clone();
     let value = if self.accept(ASSIGN) {
         self.parse_expression()?
     } else {
-        ENil
+        // No initializer. The nil value has no source of its own.
+        Spanned::new(ENil, self.previous().span)
+    };
+    self.consume(SEMICOLON, "Expect ';' after variable declaration.")?;
+    Ok(self.finish(SVar(name, value), start))
+    }
+    fn parse_function(&mut self) -> Result<Stmt, ParseError> {
+    // fun name(params) { body }
+    let start = self.peek_span();
+    self.consume(FUN, "Expected 'fun'")?;
+    let function = self.parse_function_rest("function")?;
+    Ok(self.finish(SFunction(Rc::new(function)), start))
+    }
+    // The rest of a function after the "fun".  A method in a class looks
+    // just the same, except that it has no "fun" at all.
+    fn parse_function_rest(&mut self, kind : &str) -> Result<Function, ParseError> {
+    let name = self.consume(IDENTIFIER, &format!("Expect {kind} name."))?.lexeme.clone();
+    self.consume(LPAREN, &format!("Expect '(' after {kind} name."))?;
+    let mut params = Vec::new();
+    if !self.check(RPAREN) {
+        loop {
+        if params.len() == 255 {
+            let err = self.error("Can't have more than 255 parameters.");
+            self.report(err);
+        }
+        params.push(self.consume(IDENTIFIER, "Expect parameter name.")?.lexeme.clone());
+        if !self.accept(COMMA) {
+            break;
+        }
+        }
+    }
+    self.consume(RPAREN, "Expect ')' after parameters.")?;
+    self.consume(LBRACE, &format!("Expect '{{' before {kind} body."))?;
+    // A loop around the function doesn't count inside of it
+    let enclosing = (self.in_function, self.in_loop, self.in_initializer);
+    self.in_function = true;
+    self.in_loop = false;
+    self.in_initializer = kind == "method" && name == "init";
+    let body = self.parse_statements();
+    (self.in_function, self.in_loop, self.in_initializer) = enclosing;
+    self.consume(RBRACE, "Expect '}' after block.")?;
+    Ok(Function { name, params, body })
+    }
+    fn parse_class(&mut self) -> Result<Stmt, ParseError> {
+    // class name [ < superclass ] { methods }
+    let start = self.peek_span();
+    self.consume(CLASS, "Expected 'class'")?;
+    let name = self.consume(IDENTIFIER, "Expect class name.")?.lexeme.clone();
+    let superclass = if self.accept(LT) {
+        if self.check(IDENTIFIER) && self.peek().lexeme == name {
+        let err = self.error("A class can't inherit from itself.");
+        self.report(err);
+        }
+        let span = self.consume(IDENTIFIER, "Expect superclass name.")?.span;
+        Some(Spanned::new(EName(self.previous().lexeme.clone()), span))
+    } else {
+        None
+    };
+    self.consume(LBRACE, "Expect '{' before class body.")?;
+    let enclosing = (self.in_class, self.in_subclass);
+    (self.in_class, self.in_subclass) = (true, superclass.is_some());
+    let methods = self.parse_methods();
+    (self.in_class, self.in_subclass) = enclosing;
+    let methods = methods?;
+    self.consume(RBRACE, "Expect '}' after class body.")?;
+    Ok(self.finish(SClass(Class { name, superclass, methods }), start))
+    }
+    fn parse_methods(&mut self) -> Result<Vec<Spanned<Rc<Function>>>, ParseError> {
+    let mut methods = Vec::new();
+    while !(self.check(RBRACE) || self.check(EOF)) {
+        let start = self.peek_span();
+        let method = self.parse_function_rest("method")?;
+        methods.push(self.finish(Rc::new(method), start));
+    }
+    Ok(methods)
+    }
+    fn parse_return(&mut self) -> Result<Stmt, ParseError> {
+    // return [ value ] ;
+    let start = self.peek_span();
+    if !self.in_function {
+        let err = self.error("Can't return from top-level code.");
+        self.report(err);
+    }
+    self.consume(RETURN, "Expected 'return'")?;
+    let value = if self.check(SEMICOLON) {
+        None
+    } else {
+        // An initializer always returns the new instance
+        if self.in_initializer {
+        let err = self.error("Can't return a value from an initializer.");
+        self.report(err);
+        }
+        Some(self.parse_expression()?)
     };
-    self.consume(SEMICOLON, "Expected ';'")?;
-    Ok(SVar(name, value))
+    self.consume(SEMICOLON, "Expect ';' after return value.")?;
+    Ok(self.finish(SReturn(value), start))
+    }
+    fn parse_break(&mut self) -> Result<Stmt, ParseError> {
+    // break ;  or  continue ;
+    let start = self.peek_span();
+    let (stmt, keyword) = if self.check(BREAK) { (SBreak, "break") } else { (SContinue, "continue") };
+    if !self.in_loop {
+        let err = self.error(&format!("Can't use '{keyword}' outside of a loop."));
+        self.report(err);
+    }
+    self.advance();
+    self.consume(SEMICOLON, &format!("Expect ';' after '{keyword}'."))?;
+    Ok(self.finish(stmt, start))
     }
-    fn parse_if(&mut self) -> Result<Statement, String> {
-    // if test { consequence } else { alternative }
+    // The body of a loop (where break and continue are allowed)
+    fn parse_loop_body(&mut self) -> Result<Stmt, ParseError> {
+    let enclosing = std::mem::replace(&mut self.in_loop, true);
+    let body = self.parse_statement();
+    self.in_loop = enclosing;
+    body
+    }
+    fn parse_if(&mut self) -> Result<Stmt, ParseError> {
+    // if test { consequence } [else { alternative }]   (Rublox)
+    // if (test) consequence [else alternative]        (Standard)
+    // Discussion: "else if" needs no special handling.  The alternative is
+    // just another statement, and that statement may itself be an if.  A
+    // dangling else binds to the nearest if, since that's the one still
+    // parsing when the else shows up.
+    let start = self.peek_span();
     self.consume(IF, "Expected 'if'")?;
-    let test = self.parse_expression()?;
+    let test = self.parse_condition("if")?;
     let consequence = self.parse_statement()?;
-    self.consume(ELSE, "Expected 'else'")?;
-    let alternative = self.parse_statement()?;
-    Ok(SIf(test, Box::new(consequence), Box::new(alternative)))
+    let alternative = if self.accept(ELSE) {
+        Some(Box::new(self.parse_statement()?))
+    } else {
+        None
+    };
+    Ok(self.finish(SIf(test, Box::new(consequence), alternative), start))
     }
 
-    fn parse_while(&mut self) -> Result<Statement, String> {
-    // while test { body }
+    // The test of an if or while.  Only the standard dialect wraps it in
+    // parentheses (in Rublox, any parentheses are just a group).
+    fn parse_condition(&mut self, keyword : &str) -> Result<Expr, ParseError> {
+    match self.dialect {
+        Dialect::Rublox => self.parse_expression(),
+        Dialect::Standard => {
+        self.consume(LPAREN, &format!("Expect '(' after '{keyword}'."))?;
+        let test = self.parse_expression()?;
+        self.consume(RPAREN, &format!("Expect ')' after {keyword} condition."))?;
+        Ok(test)
+        }
+    }
+    }
+
+    fn parse_while(&mut self) -> Result<Stmt, ParseError> {
+    // while test { body }     (Rublox)
+    // while (test) body       (Standard)
+    let start = self.peek_span();
     self.consume(WHILE, "Expected 'while'")?;
-    let test = self.parse_expression()?;
-    let body = self.parse_statement()?;
-    Ok(SWhile(test, Box::new(body)))
+    let test = self.parse_condition("while")?;
+    let body = self.parse_loop_body()?;
+    Ok(self.finish(SWhile(test, Box::new(body), None), start))
     }
 
-    fn parse_block(&mut self) -> Result<Statement, String> {
-    self.consume(LBRACE, "Expected '{'")?;
-    let body = self.parse_statements()?;
-    self.consume(RBRACE, "Expected '}'")?;
-    Ok(SBlock(body))
-    }
-    fn parse_statement_expr(&mut self) -> Result<Statement, String> {
-    // A bare expression like 'expr ;' or an assignment like 'lvalue = rvalue;'
-    let lvalue = self.parse_expression()?;
-    if self.accept(ASSIGN) {
-        let rvalue = self.parse_expression()?;
-        self.consume(SEMICOLON, "Expect ';' after assignment.")?;
-        Ok(SAssignment(lvalue, rvalue))
+    // Discussion: There's no for-loop in the AST.  Instead, it gets turned
+    // into the while-loop that does the same thing:
+    //
+    //      for (init; test; increment) body
+    //
+    // becomes
+    //
+    //      { init; while test { body; increment; } }
+    //
+    // The outer block keeps a variable declared by init inside the loop.
+    // Any of the three clauses can be left out.  A missing test is true.
+    // The increment has its own spot in SWhile (instead of going at the end
+    // of the body) so that a continue in the body doesn't skip over it.
+    fn parse_for(&mut self) -> Result<Stmt, ParseError> {
+    let start = self.peek_span();
+    self.consume(FOR, "Expected 'for'")?;
+    self.consume(LPAREN, "Expect '(' after 'for'.")?;
+    let init = if self.accept(SEMICOLON) {
+        None
+    } else if self.check(VAR) {
+        Some(self.parse_var()?)
+    } else {
+        Some(self.parse_statement_expr()?)
+    };
+    let test = if self.check(SEMICOLON) {
+        Spanned::new(EBoolean(true), self.peek_span())
+    } else {
+        self.parse_expression()?
+    };
+    self.consume(SEMICOLON, "Expect ';' after loop condition.")?;
+    let increment = if self.check(RPAREN) {
+        None
     } else {
-        self.consume(SEMICOLON, "Expect ';' after expression.")?;
-        Ok(SExpr(lvalue))
+        let increment = self.parse_expression()?;
+        let span = increment.span;
+        Some(Spanned::new(SExpr(increment), span))
+    };
+    self.consume(RPAREN, "Expect ')' after for clauses.")?;
+    let body = self.parse_loop_body()?;
+    let mut stmt = self.finish(SWhile(test, Box::new(body), increment.map(Box::new)), start);
+    if let Some(init) = init {
+        stmt = self.finish(SBlock(vec![init, stmt]), start);
+    }
+    Ok(stmt)
     }
+
+    fn parse_block(&mut self) -> Result<Stmt, ParseError> {
+    let start = self.peek_span();
+    self.consume(LBRACE, "Expected '{'")?;
+    let body = self.parse_statements();
+    self.consume(RBRACE, "Expect '}' after block.")?;
+    Ok(self.finish(SBlock(body), start))
+    }
+    fn parse_statement_expr(&mut self) -> Result<Stmt, ParseError> {
+    // A bare expression like 'expr ;' (an assignment is one too)
+    let expr = self.parse_expression()?;
+    let message = match expr.node {
+        EAssign(..) => "Expect ';' after assignment.",
+        _ => "Expect ';' after expression.",
+    };
+    self.consume(SEMICOLON, message)?;
+    let start = expr.span;
+    Ok(self.finish(SExpr(expr), start))
     }
 
-    // Parsing of multiple statements
-    fn parse_statements(&mut self) -> Result<Statements, String> {
+    // Parsing of multiple statements.  A statement with a syntax error is
+    // recorded and skipped so that parsing can carry on with the next one.
+    fn parse_statements(&mut self) -> Statements {
     let mut statements = Statements::new();
     while !(self.check(EOF) || self.check(RBRACE)) {
-        statements.push(self.parse_statement()?);
+        match self.parse_statement() {
+        Ok(stmt) => statements.push(stmt),
+        Err(err) => {
+            self.report(err);
+            self.synchronize();
+        }
+        }
     }
-    Ok(statements)
+    statements
+    }
+
+    // Parsing of an entire program.  Same as above, except that a '}'
+    // doesn't end anything.
+    fn parse_program(&mut self) -> Statements {
+    let mut statements = self.parse_statements();
+    while self.check(RBRACE) {
+        let err = self.error("Unexpected '}'.");
+        self.report(err);
+        self.advance();
+        statements.extend(self.parse_statements());
+    }
+    statements
     }
 }
 
+#[cfg(test)]
+// Shorthand for a variable in an expected AST
+fn name(n : &str) -> Box<Expr> {
+    Box::new(EName(String::from(n)).into())
+}
+
+#[cfg(test)]
+// The error messages from a program that doesn't parse
+fn error_messages(result : Result<AST, Vec<SyntaxError>>) -> Vec<String> {
+    result.unwrap_err().iter().map(|err| err.to_string()).collect()
+}
+
+#[cfg(test)]
+fn messages(src : &str) -> Vec<String> {
+    error_messages(parse(src))
+}
+
 #[test]
 fn test_primaries() {
     assert_eq!(parse_expression_string("1"), ENumber(1.0));
@@ -273,80 +880,408 @@ fn test_primaries() {
     assert_eq!(parse_expression_string("false"), EBoolean(false));
     assert_eq!(parse_expression_string("nil"), ENil);
     assert_eq!(parse_expression_string("xyz"), EName(String::from("xyz")));
-    // assert_eq!(parse_expression_string("\"hello\""), EString(String::from("hello")));
+    assert_eq!(parse_expression_string("\"hello\""), EString(String::from("hello")));
+    assert_eq!(parse_expression_string("\"a\\tb\""), EString(String::from("a\tb")));
 }
 
 #[test]
 fn test_unary() {
-    assert_eq!(parse_expression_string("-1"), EUnary(OpMinus, Box::new(ENumber(1.0))));
-    assert_eq!(parse_expression_string("!true"), EUnary(OpNot, Box::new(EBoolean(true))));
+    assert_eq!(parse_expression_string("-1"), EUnary(OpMinus, Box::new(ENumber(1.0).into())));
+    assert_eq!(parse_expression_string("!true"), EUnary(OpNot, Box::new(EBoolean(true).into())));
 }
 
 #[test]
 fn test_factor() {
     assert_eq!(parse_expression_string("3*4"),
            EBinary(OpMult,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
     assert_eq!(parse_expression_string("3/4"),
            EBinary(OpDiv,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
 }
 
 #[test]
 fn test_term() {
     assert_eq!(parse_expression_string("3+4"),
            EBinary(OpPlus,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
     assert_eq!(parse_expression_string("3-4"),
            EBinary(OpMinus,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
 }
 #[test]
 fn test_comparison() {
     assert_eq!(parse_expression_string("3<4"),
            EBinary(OpLt,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
     assert_eq!(parse_expression_string("3<=4"),
            EBinary(OpLe,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
     assert_eq!(parse_expression_string("3>4"),
            EBinary(OpGt,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
     assert_eq!(parse_expression_string("3>=4"),
            EBinary(OpGe,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
 }
 
 #[test]
 fn test_equality() {
     assert_eq!(parse_expression_string("3==4"),
            EBinary(OpEq,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
     assert_eq!(parse_expression_string("3!=4"),
            EBinary(OpNe,
-               Box::new(ENumber(3.0)),
-               Box::new(ENumber(4.0))));
+               Box::new(ENumber(3.0).into()),
+               Box::new(ENumber(4.0).into())));
+}
+
+#[test]
+fn test_logical() {
+    // "and" binds tighter than "or"
+    assert_eq!(parse_expression_string("a or b and c"),
+           ELogical(OpOr, name("a"),
+                Box::new(ELogical(OpAnd, name("b"), name("c")).into())));
+    assert_eq!(parse_expression_string("a and b or c"),
+           ELogical(OpOr,
+                Box::new(ELogical(OpAnd, name("a"), name("b")).into()),
+                name("c")));
+    // Both are left associative and looser than equality
+    assert_eq!(parse_expression_string("a and b and c == d"),
+           ELogical(OpAnd,
+                Box::new(ELogical(OpAnd, name("a"), name("b")).into()),
+                Box::new(EBinary(OpEq, name("c"), name("d")).into())));
+    let errors = parse("print a and;").unwrap_err();
+    assert_eq!(errors[0].to_string(), "[line 1] Error at ';': Expect expression.");
+}
+
+#[test]
+fn test_call() {
+    assert_eq!(parse_expression_string("f()"), ECall(name("f"), vec![]));
+    assert_eq!(parse_expression_string("f(1, a + b)"),
+           ECall(name("f"), vec![ENumber(1.0).into(), EBinary(OpPlus, name("a"), name("b")).into()]));
+    // Calls chain and bind tighter than unary operators
+    assert_eq!(parse_expression_string("-f(1)(2)"),
+           EUnary(OpMinus, Box::new(ECall(Box::new(ECall(name("f"), vec![ENumber(1.0).into()]).into()),
+                          vec![ENumber(2.0).into()]).into())));
+    assert_eq!(parse_expression_string("f(x)\n").span, Span::new(0, 4, 1, 1));
+
+    let args = vec!["1"; 256].join(", ");
+    let errors = parse(&format!("f({args});")).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].to_string(), "[line 1] Error at '1': Can't have more than 255 arguments.");
+    assert_eq!(parse("f(1,);").unwrap_err()[0].to_string(), "[line 1] Error at ')': Expect expression.");
+    assert_eq!(parse("f(1;").unwrap_err()[0].to_string(), "[line 1] Error at ';': Expect ')' after arguments.");
+}
+
+#[test]
+fn test_function() {
+    let ast = parse("fun add(a, b) {\n  return a + b;\n}\nfun nothing() { return; }").unwrap();
+    let SFunction(add) = &ast[0].node else { panic!("expected a function") };
+    assert_eq!(add.name, "add");
+    assert_eq!(add.params, vec!["a", "b"]);
+    assert_eq!(add.body, vec![SReturn(Some(EBinary(OpPlus,
+                               Box::new(EName(String::from("a")).into()),
+                               Box::new(EName(String::from("b")).into())).into()))]);
+    assert_eq!(ast[0].span, Span::new(0, 33, 1, 1));
+    assert_eq!(ast[1], SFunction(Rc::new(Function { name : String::from("nothing"), params : vec![], body : vec![SReturn(None).into()] })));
+
+    assert_eq!(messages("return 1;\n{ return; }"),
+           vec!["[line 1] Error at 'return': Can't return from top-level code.",
+            "[line 2] Error at 'return': Can't return from top-level code."]);
+    // Leaving a function body puts the check back on
+    assert_eq!(messages("fun f() { fun g() { return; } return; }\nreturn;"),
+           vec!["[line 2] Error at 'return': Can't return from top-level code."]);
+    assert_eq!(messages("fun (a);"), vec!["[line 1] Error at '(': Expect function name."]);
+    assert_eq!(messages("fun f(a, 1);"), vec!["[line 1] Error at '1': Expect parameter name."]);
+    assert_eq!(messages("fun f(a) print a;"), vec!["[line 1] Error at 'print': Expect '{' before function body."]);
+}
+
+#[test]
+fn test_class() {
+    let ast = parse("class Point {\n  init(x) { this.x = x; return; }\n  getx() { return this.x; }\n}\nPoint(1).getx();").unwrap();
+    let SClass(class) = &ast[0].node else { panic!("expected a class") };
+    assert_eq!(class.name, "Point");
+    let names : Vec<&str> = class.methods.iter().map(|method| method.node.name.as_str()).collect();
+    assert_eq!(names, vec!["init", "getx"]);
+    assert_eq!(class.methods[1].node.body, vec![SReturn(Some(EGet(Box::new(EThis.into()), String::from("x")).into()))]);
+    assert_eq!(class.methods[0].node.body[0],
+           SExpr(EAssign(Box::new(EGet(Box::new(EThis.into()), String::from("x")).into()),
+                         Box::new(EName(String::from("x")).into())).into()));
+    assert_eq!(ast[0].span, Span::new(0, 77, 1, 1));
+    assert_eq!(ast[1], SExpr(ECall(Box::new(EGet(Box::new(ECall(Box::new(EName(String::from("Point")).into()),
+                                          vec![ENumber(1.0).into()]).into()),
+                                  String::from("getx")).into()), vec![]).into()));
+
+    assert_eq!(messages("print this;\nfun f() { return this; }"),
+           vec!["[line 1] Error at 'this': Can't use 'this' outside of a class.",
+            "[line 2] Error at 'this': Can't use 'this' outside of a class."]);
+    assert_eq!(messages("class A { init() { return 1; } }"),
+           vec!["[line 1] Error at '1': Can't return a value from an initializer."]);
+    // Only the init() method of a class is an initializer
+    assert!(parse("class A { init() { fun f() { return 1; } return; } other() { return 2; } }\nfun init() { return 3; }").is_ok());
+    // Leaving a class puts the check on 'this' back on
+    assert_eq!(messages("class A { }\nthis;"), vec!["[line 2] Error at 'this': Can't use 'this' outside of a class."]);
+    // (Recovery picks up again at the "fun", so the class's '}' is left over)
+    assert_eq!(messages("class A { fun f() {} }"), vec!["[line 1] Error at 'fun': Expect method name.",
+                                                    "[line 1] Error at '}': Unexpected '}'."]);
+    assert_eq!(messages("class A { f() {}"), vec!["[line 1] Error at end: Expect '}' after class body."]);
+    assert_eq!(messages("a.1;"), vec!["[line 1] Error at '1': Expect property name after '.'."]);
+}
+
+#[test]
+fn test_inheritance() {
+    let ast = parse("class A { f() { } }\nclass B < A {\n  f() { return super.f(); }\n}").unwrap();
+    let SClass(class) = &ast[1].node else { panic!("expected a class") };
+    assert_eq!(class.superclass, Some(EName(String::from("A")).into()));
+    assert_eq!(class.superclass.as_ref().unwrap().span, Span::new(30, 31, 2, 11));
+    assert_eq!(class.methods[0].node.body, vec![SReturn(Some(ECall(Box::new(ESuper(String::from("f")).into()), vec![]).into()))]);
+    let SClass(class) = &ast[0].node else { panic!("expected a class") };
+    assert_eq!(class.superclass, None);
+
+    assert_eq!(messages("class A < A { }"), vec!["[line 1] Error at 'A': A class can't inherit from itself."]);
+    assert_eq!(messages("super.f();\nfun g() { super.f(); }"),
+           vec!["[line 1] Error at 'super': Can't use 'super' outside of a class.",
+            "[line 2] Error at 'super': Can't use 'super' outside of a class."]);
+    assert_eq!(messages("class A { f() { super.f(); } }"),
+           vec!["[line 1] Error at 'super': Can't use 'super' in a class with no superclass."]);
+    // A class nested inside a subclass's method has to have its own superclass
+    assert_eq!(messages("class A < B { f() { class C { g() { super.g(); } } super.f(); } }"),
+           vec!["[line 1] Error at 'super': Can't use 'super' in a class with no superclass."]);
+    assert_eq!(messages("class A < ;"), vec!["[line 1] Error at ';': Expect superclass name."]);
+    assert_eq!(messages("class A < B { f() { super; } }"), vec!["[line 1] Error at ';': Expect '.' after 'super'."]);
 }
 
 #[test]
 fn test_statement() {
     assert_eq!(parse_statement_string("print 3;"),
-           SPrint(ENumber(3.0)));
+           SPrint(ENumber(3.0).into()));
     assert_eq!(parse_statement_string("3;"),
-           SExpr(ENumber(3.0)));
+           SExpr(ENumber(3.0).into()));
     assert_eq!(parse_statement_string("var x = 3;"),
-           SVar(String::from("x"), ENumber(3.0)));
+           SVar(String::from("x"), ENumber(3.0).into()));
     assert_eq!(parse_statement_string("if true { } else { }"),
-           SIf(EBoolean(true),
-           Box::new(SBlock(Statements::new())),
-           Box::new(SBlock(Statements::new()))));
+           SIf(EBoolean(true).into(),
+           Box::new(SBlock(Statements::new()).into()),
+           Some(Box::new(SBlock(Statements::new()).into()))));
+    assert_eq!(parse_statement_string("if true { }"),
+           SIf(EBoolean(true).into(),
+           Box::new(SBlock(Statements::new()).into()),
+           None));
+    assert_eq!(parse_statement_string("if true { } else if false { } else { }"),
+           SIf(EBoolean(true).into(),
+           Box::new(SBlock(Statements::new()).into()),
+           Some(Box::new(SIf(EBoolean(false).into(),
+                     Box::new(SBlock(Statements::new()).into()),
+                     Some(Box::new(SBlock(Statements::new()).into()))).into()))));
+    // A dangling else belongs to the inner if
+    assert_eq!(parse_statement_string("if true if false print 1; else print 2;"),
+           SIf(EBoolean(true).into(),
+           Box::new(SIf(EBoolean(false).into(),
+                Box::new(SPrint(ENumber(1.0).into()).into()),
+                Some(Box::new(SPrint(ENumber(2.0).into()).into()))).into()),
+           None));
+}
+
+#[test]
+fn test_assignment() {
+    // Right-associative: a = (b = 0)
+    assert_eq!(parse_expression_string("a = b = 0"),
+               EAssign(name("a"), Box::new(EAssign(name("b"), Box::new(ENumber(0.0).into())).into())));
+    assert_eq!(parse_expression_string("a.x = 1 or 2"),
+               EAssign(Box::new(EGet(name("a"), String::from("x")).into()),
+                       Box::new(ELogical(OpOr, Box::new(ENumber(1.0).into()), Box::new(ENumber(2.0).into())).into())));
+    // Inside parentheses, it's an ordinary expression
+    assert_eq!(parse_expression_string("(a = f()) != nil"),
+               EBinary(OpNe, Box::new(EGroup(Box::new(EAssign(name("a"), Box::new(ECall(name("f"), vec![]).into())).into())).into()),
+                       Box::new(ENil.into())));
+    assert_eq!(parse_expression_string("a = 1").span, Span::new(0, 5, 1, 1));
+
+    assert_eq!(messages("1 + 2 = 3;"), vec!["[line 1] Error at '1 + 2': Invalid assignment target."]);
+    assert_eq!(messages("var a;\n(a) = 3;\nf() = 4;"),
+               vec!["[line 2] Error at '(a)': Invalid assignment target.",
+                    "[line 3] Error at 'f()': Invalid assignment target."]);
+    assert_eq!(messages("a = b + c = 1;"), vec!["[line 1] Error at 'b + c': Invalid assignment target."]);
+    // The error points at the target, not at the '='
+    let errors = parse("  1 = 2;").unwrap_err();
+    let SyntaxError::Parse(err) = &errors[0] else { panic!("expected a parse error") };
+    assert_eq!(err.span, Span::new(2, 3, 1, 3));
+    assert_eq!(err.found, NUMBER);
+    assert_eq!(messages("a = 1"), vec!["[line 1] Error at end: Expect ';' after assignment."]);
+}
+
+#[test]
+fn test_for() {
+    let print_i = || -> Stmt { SPrint(*name("i")).into() };
+    let assign = |n : &str, value : Expr| -> Stmt { SExpr(EAssign(name(n), Box::new(value)).into()).into() };
+    let increment = || -> Stmt { assign("i", EBinary(OpPlus, name("i"), Box::new(ENumber(1.0).into())).into()) };
+    let test = || -> Expr { EBinary(OpLt, name("i"), Box::new(ENumber(3.0).into())).into() };
+    assert_eq!(parse_statement_string("for (var i = 0; i < 3; i = i + 1) print i;"),
+           SBlock(vec![SVar(String::from("i"), ENumber(0.0).into()).into(),
+                   SWhile(test(), Box::new(print_i()), Some(Box::new(increment()))).into()]));
+    // Every clause is optional
+    assert_eq!(parse_statement_string("for (;;) print i;"),
+           SWhile(EBoolean(true).into(), Box::new(print_i()), None));
+    assert_eq!(parse_statement_string("for (i = 0; ; ) print i;"),
+           SBlock(vec![assign("i", ENumber(0.0).into()),
+                   SWhile(EBoolean(true).into(), Box::new(print_i()), None).into()]));
+    assert_eq!(parse_statement_string("for (; i < 3; f(i)) { }"),
+           SWhile(test(), Box::new(SBlock(vec![]).into()),
+              Some(Box::new(SExpr(ECall(name("f"), vec![*name("i")]).into()).into()))));
+    let stmt = parse_statement_string("for (var i = 0; i < 3; i = i + 1)\n  print i;");
+    assert_eq!(stmt.span, Span::new(0, 44, 1, 1));
+
+    assert_eq!(messages("for i = 0;"), vec!["[line 1] Error at 'i': Expect '(' after 'for'."]);
+    assert_eq!(messages("for (var i = 0; i < 3) { }"), vec!["[line 1] Error at ')': Expect ';' after loop condition.",
+                                                          "[line 1] Error at '}': Unexpected '}'."]);
+    assert_eq!(messages("for (;; i = i + 1 { }"), vec!["[line 1] Error at '{': Expect ')' after for clauses.",
+                                                     "[line 1] Error at '}': Unexpected '}'."]);
+}
+
+#[test]
+fn test_break() {
+    assert_eq!(parse_statement_string("while true { break; continue; }"),
+           SWhile(EBoolean(true).into(), Box::new(SBlock(vec![SBreak.into(), SContinue.into()]).into()), None));
+    assert!(parse("for (;;) if x { break; } else { while y continue; }").is_ok());
+
+    assert_eq!(messages("break;\n{ continue; }"),
+           vec!["[line 1] Error at 'break': Can't use 'break' outside of a loop.",
+            "[line 2] Error at 'continue': Can't use 'continue' outside of a loop."]);
+    // A function inside a loop is a fresh start
+    assert_eq!(messages("while true { fun f() { break; } break; }\nbreak;"),
+           vec!["[line 1] Error at 'break': Can't use 'break' outside of a loop.",
+            "[line 2] Error at 'break': Can't use 'break' outside of a loop."]);
+    assert_eq!(messages("while true break"), vec!["[line 1] Error at end: Expect ';' after 'break'."]);
+}
+
+#[test]
+fn test_spans() {
+    // 2 + 3 * 4 with the multiplication on the next line
+    let expr = parse_expression_string("2 + 3\n  * 4");
+    assert_eq!(expr.span, Span::new(0, 11, 1, 1));
+    if let EBinary(_, left, right) = &expr.node {
+    assert_eq!(left.span, Span::new(0, 1, 1, 1));
+    assert_eq!(right.span, Span::new(4, 11, 1, 5));
+    } else {
+    panic!("Expected a binary operator");
+    }
+    let stmt = parse_statement_string("while x {\n  x = (x - 1);\n}");
+    assert_eq!(stmt.span, Span::new(0, 26, 1, 1));
+    if let SWhile(_, body, _) = &stmt.node {
+    assert_eq!(body.span, Span::new(8, 26, 1, 9));
+    if let SBlock(stmts) = &body.node {
+        assert_eq!(stmts[0].span, Span::new(12, 24, 2, 3));
+    }
+    }
+}
+
+#[test]
+fn test_dialect() {
+    let print = |n : f64| -> Stmt { SPrint(ENumber(n).into()).into() };
+    let src = "if (true) print 1; else print 2;\nwhile (false) print 3;";
+    let expected = vec![SIf(EBoolean(true).into(), Box::new(print(1.0)), Some(Box::new(print(2.0)))).into(),
+                        SWhile(EBoolean(false).into(), Box::new(print(3.0)), None).into()];
+    assert_eq!(parse_with_dialect(src, Dialect::Standard), Ok(expected));
+    // Either way, the parentheses don't end up in the AST
+    assert_eq!(parse_with_dialect(src, Dialect::Rublox).map(|ast| ast.len()), Ok(2));
+
+    // Where the dialects disagree: in Rublox this is "if (x) - 1 ;"
+    let src = "var x = 1; if (x) -1;";
+    assert_eq!(messages(src), vec!["[line 1] Error at ';': Expect expression."]);
+    assert!(parse_with_dialect(src, Dialect::Standard).is_ok());
+    assert_eq!(error_messages(parse_with_dialect("if true print 1;", Dialect::Standard)),
+               vec!["[line 1] Error at 'true': Expect '(' after 'if'."]);
+    assert_eq!(error_messages(parse_with_dialect("while (true print 1;", Dialect::Standard)),
+               vec!["[line 1] Error at 'print': Expect ')' after while condition."]);
+
+    // The pragma on the first line overrides the default
+    let src = "// lox-dialect: standard\nif (true) print 1;";
+    assert_eq!(dialect_pragma(src), Some("standard"));
+    assert_eq!(parse(src).map(|ast| ast.len()), Ok(1));
+    assert_eq!(parse_with_dialect("//lox-dialect:rublox\nif true { }", Dialect::Standard).map(|ast| ast.len()), Ok(1));
+    // ... but only on the first line
+    assert_eq!(dialect_pragma("print 1;\n// lox-dialect: standard"), None);
+    assert_eq!(messages("// lox-dialect: python\nprint 1;"),
+               vec!["[line 1] Error at '// lox-dialect: python': Unknown dialect 'python'."]);
+    assert_eq!("standard".parse(), Ok(Dialect::Standard));
+}
+
+#[test]
+fn test_parse_errors() {
+    // Every error in the program gets reported, not just the first
+    let errors = parse("print 1\nvar = 2;\nprint 3;\nprint (4;\n{ print 5 }").unwrap_err();
+    let messages : Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages, vec!["[line 2] Error at 'var': Expect ';' after expression.",
+                              "[line 2] Error at '=': Expect variable name.",
+                              "[line 4] Error at ';': Expect ')' after expression.",
+                              "[line 5] Error at '}': Expect ';' after expression."]);
+    let SyntaxError::Parse(err) = &errors[1] else { panic!("expected a parse error") };
+    assert_eq!(err.span, Span::new(12, 13, 2, 5));
+    assert_eq!(err.found, ASSIGN);
+    assert_eq!(err.expected, vec![IDENTIFIER]);
+    // After "print 1", an operator or a semicolon would have been fine
+    let SyntaxError::Parse(err) = &errors[0] else { panic!("expected a parse error") };
+    assert!(err.expected.contains(&SEMICOLON));
+    assert!(err.expected.contains(&PLUS));
+
+    let errors = parse("print 1 +").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].to_string(), "[line 1] Error at end: Expect expression.");
+    assert!(matches!(&errors[0], SyntaxError::Parse(err) if err.found == EOF));
+
+    let errors = parse("} print 1;").unwrap_err();
+    assert_eq!(errors[0].to_string(), "[line 1] Error at '}': Unexpected '}'.");
+
+    assert!(parse("print 1; { print 2; }").is_ok());
+}
+
+#[test]
+fn test_interpolation() {
+    assert_eq!(parse_expression_string("\"total = ${n * 2}!\""),
+               EInterpolate(vec![(String::from("total = "), EBinary(OpMult, name("n"), Box::new(ENumber(2.0).into())).into())],
+                            String::from("!")));
+    // Braces inside of the expression don't end it
+    assert_eq!(parse_expression_string("\"${a}${ \"{${b}}\" }\""),
+               EInterpolate(vec![(String::new(), *name("a")),
+                                 (String::new(), EInterpolate(vec![(String::from("{"), *name("b"))], String::from("}")).into())],
+                            String::new()));
+    let expr = parse_expression_string("\"x${y}z\" + 1");
+    assert_eq!(expr.span, Span::new(0, 12, 1, 1));
+
+    assert_eq!(messages("print \"a ${1 2}\";\nprint \"b ${}\";"), vec!["[line 1] Error at '2': Expect '}' after interpolated expression.",
+                              "[line 2] Error at '}\"': Expect expression."]);
+    // The rest of the string after a '}' isn't a string of its own
+    assert_eq!(messages("print \"${\"a\" + }\" \"x\";"), vec!["[line 1] Error at '}\"': Expect expression."]);
+    assert_eq!(messages("print \"a ${1 + } b\";"), vec!["[line 1] Error at '} b\"': Expect expression."]);
+    assert_eq!(messages("print \"${1 + }${2}\";"), vec!["[line 1] Error at '}${': Expect expression."]);
+}
+
+#[test]
+fn test_lazy_tokens() {
+    // Lexical errors come out mixed in with syntax errors, in source order
+    assert_eq!(messages("print 1 # 2;\nprint @;"), vec!["[line 1, column 9] Error: Unexpected character '#'.",
+                              "[line 1] Error at '2': Expect ';' after expression.",
+                              "[line 2, column 7] Error: Unexpected character '@'.",
+                              "[line 2] Error at ';': Expect expression."]);
+
+    // The scanner is only run as far as the parser needs
+    let mut parser = Parser::new(Scanner::new("1 + 2; @ @"));
+    assert_eq!(parser.parse_expression(), Ok(EBinary(OpPlus, Box::new(ENumber(1.0).into()), Box::new(ENumber(2.0).into())).into()));
+    assert!(parser.errors.is_empty());
+    assert_eq!(parser.lookahead.len(), 1);
+
+    // Any stream of tokens will do, even one with no EOF at the end
+    let tokens = vec![Token::new(PRINT, "print", Span::new(0, 5, 1, 1)),
+                      Token::with_literal(NUMBER, "1", Literal::LitNumber(1.0), Span::new(6, 7, 1, 7))];
+    let errors = parse_tokens(tokens.into_iter().map(Ok), Dialect::Rublox).unwrap_err();
+    assert_eq!(errors[0].to_string(), "[line 1] Error at end: Expect ';' after expression.");
 }
@@ -2,152 +2,263 @@
 //
 // Tokenize Lox
 
-use std::str::Chars;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
 
-use crate::{Source, Tokens, Token};
+use crate::{Literal, Source, Span, Tokens, Token, TokenType};
 use crate::TokenType::*;
 
-pub fn tokenize(src: &Source) -> Tokens {
-    println!("Tokenizing Lox");
-    let mut scanner = Scanner::new(String::from(src));
-    let toks = scanner.tokenize();
-    println!("{toks:?}");
-    toks
+// Tokenize the whole source into a list (ending with EOF).  If there's
+// anything in it that isn't a valid token, all of the problems found are
+// returned instead.  The parser doesn't need this (it pulls tokens straight
+// from a Scanner), but it's handy for tests and tools.
+pub fn tokenize(src: &Source) -> Result<Tokens, Vec<LexError>> {
+    collect_tokens(Scanner::new(src))
 }
 
-struct Scanner {
-    source : String,       // Input text
-    index : usize,         // Current scan position
+// Tokenize the source without losing anything.  Every token carries the
+// whitespace and comments around it, so untokenize() gives back the source
+// exactly as it was.  Tools that rewrite source (formatters, refactoring
+// tools, syntax highlighters, etc.) want this.
+pub fn tokenize_lossless(src: &Source) -> Result<Tokens, Vec<LexError>> {
+    collect_tokens(Scanner::lossless(src))
 }
 
-impl Scanner {
-    fn new(source : String) -> Scanner {
-    Scanner { source, index: 0 }
+// Put the text of lossless tokens back together
+pub fn untokenize(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    for tok in tokens {
+    for trivia in tok.leading.iter().chain(std::iter::once(tok)).chain(tok.trailing.iter()) {
+        text.push_str(&trivia.lexeme);
     }
-    fn peekch(&self) -> char {
-    if self.index >= self.source.len() {
-        '\x00'
-    } else {
-        self.source.chars().nth(self.index).expect("")
     }
+    text
+}
+
+fn collect_tokens(scanner : Scanner) -> Result<Tokens, Vec<LexError>> {
+    let mut toks = Tokens::new();
+    let mut errors = Vec::new();
+    for result in scanner {
+    match result {
+        Ok(tok) => toks.push(tok),
+        Err(err) => errors.push(err),
     }
-    fn peek(&self, n : usize) -> &str {
-    if self.index + n >= self.source.len() {
-        ""
+    }
+    if errors.is_empty() {
+    Ok(toks)
     } else {
-        &self.source[self.index..self.index+n]
+    Err(errors)
+    }
+}
+
+// Discussion: Bad input (a stray '@', a string with no closing quote, etc.)
+// shouldn't make the scanner quietly stop.  Instead, a LexError is recorded
+// for the offending text, the scanner skips over it, and keeps on going.
+#[derive(PartialEq, Debug)]
+pub struct LexError {
+    pub message : String,
+    pub lexeme : String,       // The offending text
+    pub span : Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "[line {}, column {}] Error: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+// Discussion: The scanner is a cursor that moves forward over the characters
+// of the source one at a time and never backs up (at most, it peeks a couple
+// of characters ahead).  That makes scanning O(n) in the size of the input.
+//
+// The cursor steps over chars, not bytes, so non-ASCII text (names like café,
+// strings with emoji, etc.) comes out right.  However, positions are kept as
+// byte offsets so that the text of a token can be sliced straight out of the
+// source.  Line and column numbers are counted as the cursor moves along.
+//
+// To scan a token, next_token() remembers where the token starts, consumes
+// its first character, and then hands off to one of the match_* functions to
+// consume the rest.  The token is whatever text got consumed along the way.
+//
+// A Scanner is also an Iterator that produces tokens on demand.  It skips
+// whitespace and comments, hands back a LexError for any bad text (and then
+// keeps going), and finishes with a single EOF token.
+//
+// Discussion: A lossless scanner doesn't throw whitespace and comments away.
+// Instead, they get attached to the neighboring tokens as "trivia" (the name
+// Roslyn and Swift use for this).  A token's trailing trivia is everything
+// after it up to and including the end of its line.  Anything else goes in
+// the leading trivia of the token that follows (the EOF token picks up
+// whatever is left at the end).  For example, in
+//
+//      x = 1;   // one
+//      // two
+//      print x;
+//
+// the ';' token has "   // one\n" as trailing trivia and the 'print' token
+// has "// two\n" as leading trivia.  Every byte of the source ends up in
+// exactly one place, so nothing is lost.  That goes for bad text too.  The
+// scanner still reports it as an error, but it also keeps a copy as an ERROR
+// trivia token so that the source can be put back together exactly.
+pub struct Scanner<'a> {
+    source : &'a str,                    // Input text
+    chars : Peekable<CharIndices<'a>>,   // Cursor over the input
+    index : usize,         // Current scan position (byte offset)
+    line : usize,          // Line number of the current position
+    column : usize,        // Column number of the current position
+    start : Span,          // Where the token being scanned started
+    finished : bool,       // Has the EOF token been handed out?
+    lossless : bool,       // Keep whitespace and comments as trivia?
+    leading : Tokens,      // Trivia waiting for the next token
+    pending : Option<Result<Token, LexError>>,  // Scanned while looking for trailing trivia
+    interpolations : Vec<usize>,  // Open '{' count for each ${ we're inside of
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(source : &'a str) -> Scanner<'a> {
+    Scanner {
+        source,
+        chars : source.char_indices().peekable(),
+        index : 0,
+        line : 1,
+        column : 1,
+        start : Span::new(0, 0, 1, 1),
+        finished : false,
+        lossless : false,
+        leading : Tokens::new(),
+        pending : None,
+        interpolations : Vec::new(),
     }
     }
 
-    fn remaining(&self) -> Chars {
-    self.source[self.index..].chars()
+    pub fn lossless(source : &'a str) -> Scanner<'a> {
+    Scanner { lossless : true, ..Scanner::new(source) }
     }
 
-    // Return the next token on the input.
-    fn next_token(&mut self) -> Option<Token> {
-    if let Some(tok) = self.match_any() {
-        self.index += tok.lexeme.len();
-        Some(tok)
+    // Look at the next character without consuming it
+    fn peek(&mut self) -> Option<char> {
+    self.chars.peek().map(|&(_, ch)| ch)
+    }
+
+    // Look at the character after the next one
+    fn peek_next(&self) -> Option<char> {
+    self.source[self.index..].chars().nth(1)
+    }
+
+    // Consume the next character, keeping track of the line and column
+    fn advance(&mut self) -> Option<char> {
+    let (_, ch) = self.chars.next()?;
+    self.index += ch.len_utf8();
+    if ch == '\n' {
+        self.line += 1;
+        self.column = 1;
     } else {
-        None
+        self.column += 1;
+    }
+    Some(ch)
+    }
+
+    // Consume the next character, but only if it's the one wanted
+    fn accept(&mut self, ch : char) -> bool {
+    if self.peek() == Some(ch) {
+        self.advance();
+        true
+    } else {
+        false
     }
     }
-    fn tokenize(&mut self) -> Tokens {
-    let mut rawtokens = Tokens::new();
-    // Phase 1: Collect all of the tokens into a list
-    while let Some(tok) = self.next_token() {
-        rawtokens.push(tok);
+
+    // Consume characters for as long as they pass a test
+    fn accept_while(&mut self, test : impl Fn(char) -> bool) {
+    while self.peek().is_some_and(&test) {
+        self.advance();
     }
-    // Phase 2: Fix all of the line numbers, throw away whitespace and comments
-    let mut tokens = Tokens::new();
-    let mut line = 1;
-    for tok in rawtokens.into_iter() {
-        match tok {
-        Token { toktype: WHITESPACE, lexeme, line:_ } => {
-            line += lexeme.matches('\n').count();
-        },
-        Token { toktype: COMMENT, lexeme: _, line: _ } => {
-        },
-        _ => {
-            tokens.push(Token::new(tok.toktype, &tok.lexeme, line as i32))
-        }
-        }
     }
-    tokens
-    }
-
-    fn match_any(&self) -> Option<Token> {
-    // Discussion.  Can this code be simplified in some way?  Higher-order functions?
-    if let Some(tok) = self.match_whitespace() {
-        return Some(tok);
-    }
-    if let Some(tok) = self.match_comment() {
-        return Some(tok);
-    }
-    if let Some(tok) = self.match_identifier() {
-        return Some(tok);
-    }
-    if let Some(tok) = self.match_number() {
-        return Some(tok);
-    }
-    if let Some(tok) = self.match_string() {
-        return Some(tok);
-    }
-    if let Some(tok) = self.match_two_character_symbol() {
-        return Some(tok);
-    }
-    if let Some(tok) = self.match_one_character_symbol() {
-        return Some(tok);
-    }
-    return None
-    }
-    // Match any single character symbol like "+", ".", etc.
-    fn match_one_character_symbol(&self) -> Option<Token> {
-    match self.peekch() {
-        '+' => Some(Token::new(PLUS, "+", 0)),
-        '-' => Some(Token::new(MINUS, "-", 0)),
-        '*' => Some(Token::new(STAR, "*", 0)),
-        '(' => Some(Token::new(LPAREN, "(", 0)),
-        ')' => Some(Token::new(RPAREN, ")", 0)),
-        '{' => Some(Token::new(LBRACE, "{", 0)),
-        '}' => Some(Token::new(RBRACE, "}", 0)),
-        ';' => Some(Token::new(SEMICOLON, ";", 0)),
-        ',' => Some(Token::new(COMMA, ",", 0)),
-        '.' => Some(Token::new(DOT, ".", 0)),
-        '=' => Some(Token::new(ASSIGN, "=", 0)),
-        '>' => Some(Token::new(GT, ">", 0)),
-        '<' => Some(Token::new(LT, "<", 0)),
-        '!' => Some(Token::new(BANG, "!", 0)),
-        '/' => Some(Token::new(SLASH, "/", 0)),
-        _ => None
-    }
-    }
-    // Match any two-character symbol like "<=", "==", "!=", etc.
-    fn match_two_character_symbol(&self) -> Option<Token> {
-    match self.peek(2) {
-        "<=" => Some(Token::new(LE, "<=", 0)),
-        ">=" => Some(Token::new(GE, ">=", 0)),
-        "!=" => Some(Token::new(NE, "!=", 0)),
-        "==" => Some(Token::new(EQ, "==", 0)),
-        _ => None
-    }
-    }
-    fn match_identifier(&self) -> Option<Token> {
-    let ch = self.peekch();
-    if !(ch.is_alphabetic() || ch == '_') {
-        return None
-    }
-    let mut lexeme = String::new();
-    for ch in self.remaining() {
-        if ch.is_alphanumeric() || ch == '_' {
-        lexeme.push(ch);
-        } else {
-        break;
+
+    // The text consumed since the start of the current token
+    fn lexeme(&self) -> &'a str {
+    &self.source[self.start.start..self.index]
+    }
+
+    fn span(&self) -> Span {
+    Span { end : self.index, ..self.start }
+    }
+
+    // Make a token (or an error) out of the text consumed so far
+    fn token(&self, toktype : TokenType) -> Token {
+    Token::new(toktype, self.lexeme(), self.span())
+    }
+
+    fn error(&self, message : &str) -> LexError {
+    LexError { message : String::from(message), lexeme : String::from(self.lexeme()), span : self.span() }
+    }
+
+    // Return the next token on the input (or None at the end).
+    fn next_token(&mut self) -> Option<Result<Token, LexError>> {
+    self.start = Span::new(self.index, self.index, self.line, self.column);
+    let ch = self.advance()?;
+    let result = if ch.is_whitespace() {
+        Ok(self.match_whitespace(ch))
+    } else if ch == '/' && self.peek() == Some('/') {
+        Ok(self.match_comment())
+    } else if ch == '/' && self.peek() == Some('*') {
+        self.match_block_comment()
+    } else if ch.is_alphabetic() || ch == '_' {
+        Ok(self.match_identifier())
+    } else if ch.is_ascii_digit() {
+        self.match_number(ch)
+    } else if ch == '"' {
+        self.match_string(false)
+    } else if ch == '}' && self.interpolations.last() == Some(&0) {
+        // The end of a ${ ... } inside a string.  Pick up the string again.
+        self.interpolations.pop();
+        self.match_string(true)
+    } else {
+        self.match_symbol(ch)
+    };
+    Some(result)
+    }
+
+    // Match a symbol like "+", "<=", etc.  ch is its first character.
+    fn match_symbol(&mut self, ch : char) -> Result<Token, LexError> {
+    let toktype = match ch {
+        '+' => PLUS,
+        '-' => MINUS,
+        '*' => STAR,
+        '(' => LPAREN,
+        ')' => RPAREN,
+        '{' => {
+        if let Some(depth) = self.interpolations.last_mut() {
+            *depth += 1;
         }
+        LBRACE
+        },
+        '}' => {
+        if let Some(depth) = self.interpolations.last_mut() {
+            *depth -= 1;
+        }
+        RBRACE
+        },
+        ';' => SEMICOLON,
+        ',' => COMMA,
+        '.' => DOT,
+        '/' => SLASH,
+        '=' => if self.accept('=') { EQ } else { ASSIGN },
+        '>' => if self.accept('=') { GE } else { GT },
+        '<' => if self.accept('=') { LE } else { LT },
+        '!' => if self.accept('=') { NE } else { BANG },
+        _ => return Err(self.error(&format!("Unexpected character '{ch}'.")))
+    };
+    Ok(self.token(toktype))
     }
-    let toktype = match lexeme.as_str() {
+    fn match_identifier(&mut self) -> Token {
+    self.accept_while(|ch| ch.is_alphanumeric() || ch == '_');
+    let toktype = match self.lexeme() {
         "and" => AND,
+        "break" => BREAK,
         "class" => CLASS,
+        "continue" => CONTINUE,
         "else" => ELSE,
         "false" => FALSE,
         "for" => FOR,
@@ -164,70 +275,238 @@ impl Scanner {
         "while" => WHILE,
         _ => IDENTIFIER
     };
-    Some(Token::new(toktype, &lexeme, 0))
-    }
-
-    // not super happy with this, may revisit later
-    fn match_number(&self) -> Option<Token> {
-    if !(self.peekch().is_numeric()) {
-        return None;
-    }
-    let mut have_decimal_point = false;
-    let mut lexeme = String::new();
-    for ch in self.remaining() {
-        if ch == '.' && have_decimal_point {
-        break;
-        } else if ch == '.' {
-        have_decimal_point = true;
-        } else if !(ch.is_numeric()) {
-        break;
+    self.token(toktype)
+    }
+
+    // Match a number.  ch is its first digit.  Numbers can be written in
+    // decimal (with an optional fraction and exponent, like 1.5e-3), in hex
+    // (0xff) or in binary (0b1010).  Underscores can split up the digits
+    // (1_000_000), but only between two digits.  A '.' is only part of the
+    // number if a digit comes after it.  That way, 123.abs() is the number
+    // 123 followed by a method call.
+    //
+    // The token's literal holds the value.  Hex and binary numbers have to
+    // fit in 53 bits so that they can be stored exactly.
+    fn match_number(&mut self, ch : char) -> Result<Token, LexError> {
+    let radix = match (ch, self.peek()) {
+        ('0', Some('x' | 'X')) => 16,
+        ('0', Some('b' | 'B')) => 2,
+        _ => 10,
+    };
+    let mut wellformed;
+    if radix == 10 {
+        wellformed = self.accept_digits(10, self.start.start);
+        if self.peek() == Some('.') && self.peek_next().is_some_and(|ch| ch.is_ascii_digit()) {
+        self.advance();
+        wellformed &= self.accept_digits(10, self.index);
         }
-        lexeme.push(ch);
+        if matches!(self.peek(), Some('e' | 'E')) {
+        self.advance();
+        if !self.accept('+') {
+            self.accept('-');
+        }
+        wellformed &= self.accept_digits(10, self.index);
+        }
+    } else {
+        self.advance();
+        wellformed = self.accept_digits(radix, self.index);
+    }
+    // A number running straight into a name (like 123abc or 0b102) is malformed
+    if !wellformed || self.peek().is_some_and(|ch| ch.is_alphanumeric() || ch == '_') {
+        self.accept_while(|ch| ch.is_alphanumeric() || ch == '_');
+        return Err(self.error(&format!("Malformed number '{}'.", self.lexeme())));
+    }
+    let digits : String = self.lexeme().chars().filter(|&ch| ch != '_').collect();
+    let value = if radix == 10 {
+        digits.parse::<f64>().ok().filter(|value| value.is_finite())
+    } else {
+        u64::from_str_radix(&digits[2..], radix).ok()
+        .filter(|&value| value <= 1 << 53)
+        .map(|value| value as f64)
+    };
+    match value {
+        Some(value) => Ok(Token::with_literal(NUMBER, self.lexeme(), Literal::LitNumber(value), self.span())),
+        None => Err(self.error(&format!("Number '{}' is out of range.", self.lexeme()))),
+    }
+    }
+
+    // Consume a run of digits in some radix (along with any underscores).
+    // Returns whether everything from start on is a proper group of digits.
+    // That is, there's at least one digit and any underscores sit between
+    // two digits.
+    fn accept_digits(&mut self, radix : u32, start : usize) -> bool {
+    self.accept_while(|ch| ch.is_digit(radix) || ch == '_');
+    let group = &self.source[start..self.index];
+    !group.is_empty() && !group.starts_with('_') && !group.ends_with('_') && !group.contains("__")
+    }
+
+    fn match_comment(&mut self) -> Token {
+    self.accept_while(|ch| ch != '\n');
+    self.token(COMMENT)
+    }
+
+    // Match a /* block comment */.  Block comments nest, so /* a /* b */ c */
+    // is all one comment.  They can also span lines (advance() counts them).
+    fn match_block_comment(&mut self) -> Result<Token, LexError> {
+    self.advance();         // The '*' of the opening "/*"
+    let mut depth = 1;
+    while depth > 0 {
+        match self.advance() {
+        Some('/') if self.accept('*') => depth += 1,
+        Some('*') if self.accept('/') => depth -= 1,
+        Some(_) => { },
+        None => return Err(self.error("Unterminated block comment.")),
+        }
+    }
+    Ok(self.token(COMMENT))
+    }
+    // A run of whitespace stops at the end of a line (so that trailing trivia
+    // can end there too)
+    fn match_whitespace(&mut self, ch : char) -> Token {
+    if ch != '\n' {
+        self.accept_while(|ch| ch.is_whitespace() && ch != '\n');
+        self.accept('\n');
     }
-    Some(Token::new(NUMBER, &lexeme, 0))
+    self.token(WHITESPACE)
     }
 
-    fn match_comment(&self) -> Option<Token> {
-    if self.peek(2) == "//" {
-        let mut lexeme = String::new();
-        for ch in self.remaining() {
-        if ch == '\n' {
+    // Collect trivia following a token, up to the end of its line
+    fn trailing_trivia(&mut self) -> Tokens {
+    let mut trivia = Tokens::new();
+    loop {
+        match self.next_token() {
+        Some(Ok(tok)) if tok.is_trivia() => {
+            let eol = tok.lexeme.contains('\n');
+            trivia.push(tok);
+            if eol {
+            break;
+            }
+        }
+        other => {
+            self.pending = other;
             break;
         }
-        lexeme.push(ch);
         }
-        Some(Token::new(COMMENT, &lexeme, 0))
-    } else {
-        None
-    }
     }
-    fn match_whitespace(&self) -> Option<Token> {
-    if !(self.peekch().is_whitespace()) {
-        return None;
+    trivia
     }
-    let mut lexeme = String::new();
-    for ch in self.remaining() {
-        if ch.is_whitespace() {
-        lexeme.push(ch);
-        } else {
-        break;
+
+    // Match a string literal like "hello\n".  Strings can span multiple lines
+    // and can contain the escape sequences \n \t \r \" \\ \$ and \u{XXXX}.
+    // The lexeme is the exact source text (quotes and all). The token's
+    // literal holds the actual string value with the escapes decoded.
+    //
+    // Discussion: A string like "total = ${n * 2}!" has an expression inside
+    // of it.  The scanner splits it into pieces:
+    //
+    //      INTERPOLATION   "total = ${      (value is "total = ")
+    //      IDENTIFIER      n
+    //      STAR            *
+    //      NUMBER          2
+    //      STRINGEND       }!"              (value is "!")
+    //
+    // The pieces that pick up again after a '}' get their own token types
+    // (STRINGMID if another ${ follows, STRINGEND at the closing quote).
+    // Otherwise the parser couldn't tell }!" from an ordinary string, and
+    // "${1 + }!" would parse as 1 + "!".  continued is true for those
+    // pieces.  The scanner has to know which '}' ends an expression (as
+    // opposed to being part of it), so it counts the braces opened inside
+    // of each ${ that it's in.  Interpolations can nest.
+    fn match_string(&mut self, continued : bool) -> Result<Token, LexError> {
+    let mut value = String::new();
+    let mut error : Option<String> = None;    // First bad escape sequence (if any)
+    let toktype = loop {
+        match self.advance() {
+        Some('"') => break Some(if continued { STRINGEND } else { STRING }),
+        Some('$') if self.accept('{') => {
+            self.interpolations.push(0);
+            break Some(if continued { STRINGMID } else { INTERPOLATION });
+        },
+        Some('\\') => {
+            match self.advance() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('r') => value.push('\r'),
+            Some('"') => value.push('"'),
+            Some('\\') => value.push('\\'),
+            Some('$') => value.push('$'),
+            Some('u') => {
+                match self.match_unicode_escape() {
+                Some(uch) => value.push(uch),
+                None => {
+                    error.get_or_insert(String::from("Invalid unicode escape sequence."));
+                }
+                }
+            },
+            Some(other) => {
+                error.get_or_insert(format!("Invalid escape sequence '\\{other}'."));
+            },
+            None => break None
+            }
+        },
+        Some(ch) => value.push(ch),
+        None => break None
         }
+    };
+    let Some(toktype) = toktype else {
+        // No closing quote. Everything up to the end of the input is lost.
+        return Err(self.error("Unterminated string."));
+    };
+    match error {
+        Some(message) => Err(self.error(&message)),
+        None => Ok(Token::with_literal(toktype, self.lexeme(), Literal::LitString(value), self.span()))
     }
-    Some(Token::new(WHITESPACE, &lexeme, 0))
     }
 
-    fn match_string(&self) -> Option<Token> {
-    if self.peekch() != '\"' {
-        return None;
+    // The rest of a \u{XXXX} escape (after the \u). XXXX is 1-6 hex digits
+    // naming a unicode character.
+    fn match_unicode_escape(&mut self) -> Option<char> {
+    let opened = self.accept('{');
+    let start = self.index;
+    self.accept_while(|ch| ch.is_ascii_hexdigit());
+    let digits = &self.source[start..self.index];
+    let closed = self.accept('}');
+    if opened && closed && (1..=6).contains(&digits.len()) {
+        u32::from_str_radix(digits, 16).ok().and_then(char::from_u32)
+    } else {
+        None
     }
-    let mut lexeme = String::new();
-    for ch in self.remaining() {
-        lexeme.push('"');
-        if ch == '"' && lexeme.len() > 0 {
-        break;
+    }
+}
+
+impl Iterator for Scanner<'_> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+    let mut tok = loop {
+        match self.pending.take().or_else(|| self.next_token()) {
+        Some(Ok(tok)) if tok.is_trivia() => {
+            if self.lossless {
+            self.leading.push(tok);
+            }
+        }
+        Some(Ok(tok)) => break tok,
+        Some(Err(err)) => {
+            if self.lossless {
+            self.leading.push(Token::new(ERROR, &err.lexeme, err.span));
+            }
+            return Some(Err(err));
+        }
+        None if self.finished => return None,
+        None => {
+            // next_token() left the start of the token at the end of the input
+            self.finished = true;
+            break self.token(EOF);
+        }
+        }
+    };
+    if self.lossless {
+        tok.leading = std::mem::take(&mut self.leading);
+        if tok.toktype != EOF {
+        tok.trailing = self.trailing_trivia();
         }
     }
-    Some(Token::new(STRING, &lexeme, 0))
+    Some(Ok(tok))
     }
 }
 
@@ -237,48 +516,277 @@ mod tests {
 
 #[test]
 fn test_scanner() {
-    let mut scan = Scanner::new(String::from("hello world"));
-    assert_eq!(scan.peek(1), "h");
-    assert_eq!(scan.peek(2), "he");
+    let mut scan = Scanner::new("hé\nllo");
+    assert_eq!(scan.peek(), Some('h'));
+    assert_eq!(scan.advance(), Some('h'));
+    assert_eq!(scan.advance(), Some('é'));
+    assert_eq!((scan.index, scan.line, scan.column), (3, 1, 3));
+    assert!(!scan.accept('l'));
+    assert!(scan.accept('\n'));
+    assert_eq!((scan.index, scan.line, scan.column), (4, 2, 1));
+    scan.accept_while(|ch| ch == 'l');
+    assert_eq!(scan.peek(), Some('o'));
+    scan.advance();
+    assert_eq!(scan.advance(), None);
 }
 
 #[test]
-fn test_match_one_character_symbol() {
-    let scanner = Scanner::new(String::from("+*"));
-    let t = scanner.match_one_character_symbol();
-    assert_eq!(t, Some(Token::new(PLUS, "+", 0)));
-
-    let scanner = Scanner::new(String::from("a"));
-    let t = scanner.match_one_character_symbol();
-    assert_eq!(t, None);
-}
-
-#[test]
-fn test_match_two_character_symbol() {
-    let scanner = Scanner::new(String::from("<= "));
-    let t = scanner.match_two_character_symbol();
-    assert_eq!(t, Some(Token::new(LE, "<=", 0)));
+fn test_match_symbol() {
+    let toks = tokenize(&String::from("+*<<=>>=!!====/.")).unwrap();
+    let types : Vec<TokenType> = toks.iter().map(|tok| tok.toktype).collect();
+    assert_eq!(types, vec![PLUS, STAR, LT, LE, GT, GE, BANG, NE, EQ, ASSIGN, SLASH, DOT, EOF]);
+    // A two-character symbol right at the end of the input
+    let toks = tokenize(&String::from("a<=")).unwrap();
+    assert_eq!(toks[1], Token::new(LE, "<=", Span::new(1, 3, 1, 2)));
 }
 
 #[test]
 fn test_next_token() {
-    let mut scanner = Scanner::new(String::from("<<=123 1234.56 \nabc//comment"));
+    let mut scanner = Scanner::new("<<=123 1234.56 \nabc//comment");
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(LT, "<", 0)));
+    assert_eq!(t, Some(Ok(Token::new(LT, "<", Span::new(0, 1, 1, 1)))));
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(LE, "<=", 0)));
+    assert_eq!(t, Some(Ok(Token::new(LE, "<=", Span::new(1, 3, 1, 2)))));
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(NUMBER, "123", 0)));
+    assert_eq!(t, Some(Ok(Token::with_literal(NUMBER, "123", Literal::LitNumber(123.0), Span::new(3, 6, 1, 4)))));
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(WHITESPACE, " ", 0)));
+    assert_eq!(t, Some(Ok(Token::new(WHITESPACE, " ", Span::new(6, 7, 1, 7)))));
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(NUMBER, "1234.56", 0)));
+    assert_eq!(t, Some(Ok(Token::with_literal(NUMBER, "1234.56", Literal::LitNumber(1234.56), Span::new(7, 14, 1, 8)))));
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(WHITESPACE, " \n", 0)));
+    assert_eq!(t, Some(Ok(Token::new(WHITESPACE, " \n", Span::new(14, 16, 1, 15)))));
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(IDENTIFIER, "abc", 0)));
+    assert_eq!(t, Some(Ok(Token::new(IDENTIFIER, "abc", Span::new(16, 19, 2, 1)))));
     let t = scanner.next_token();
-    assert_eq!(t, Some(Token::new(COMMENT, "//comment", 0)));
+    assert_eq!(t, Some(Ok(Token::new(COMMENT, "//comment", Span::new(19, 28, 2, 4)))));
+    assert_eq!(scanner.next_token(), None);
+}
+
+#[test]
+fn test_tokenize_spans() {
+    let toks = tokenize(&String::from("var x\n  = 1;")).unwrap();
+    let spans : Vec<Span> = toks.iter().map(|tok| tok.span).collect();
+    assert_eq!(spans, vec![Span::new(0, 3, 1, 1),
+               Span::new(4, 5, 1, 5),
+               Span::new(8, 9, 2, 3),
+               Span::new(10, 11, 2, 5),
+               Span::new(11, 12, 2, 6),
+               Span::new(12, 12, 2, 7)]);
+}
+
+#[test]
+fn test_scanner_iterator() {
+    let scanner = Scanner::new("x = # 1; // done\n");
+    let results : Vec<Result<Token, LexError>> = scanner.collect();
+    assert_eq!(results.len(), 6);
+    assert_eq!(results[0], Ok(Token::new(IDENTIFIER, "x", Span::new(0, 1, 1, 1))));
+    assert_eq!(results[1], Ok(Token::new(ASSIGN, "=", Span::new(2, 3, 1, 3))));
+    assert!(matches!(&results[2], Err(err) if err.lexeme == "#"));
+    assert_eq!(results[3], Ok(Token::with_literal(NUMBER, "1", Literal::LitNumber(1.0), Span::new(6, 7, 1, 7))));
+    assert_eq!(results[4], Ok(Token::new(SEMICOLON, ";", Span::new(7, 8, 1, 8))));
+    assert_eq!(results[5], Ok(Token::new(EOF, "", Span::new(17, 17, 2, 1))));
+
+    // Nothing more after EOF
+    let mut scanner = Scanner::new("");
+    assert_eq!(scanner.next(), Some(Ok(Token::new(EOF, "", Span::new(0, 0, 1, 1)))));
+    assert_eq!(scanner.next(), None);
+    assert_eq!(scanner.next(), None);
+}
+
+#[test]
+fn test_non_ascii() {
+    // Byte offsets and (character) columns both come out right
+    let toks = tokenize(&String::from("var café = \"naïve ☃\"; // ü\nπ;")).unwrap();
+    assert_eq!(toks[1], Token::new(IDENTIFIER, "café", Span::new(4, 9, 1, 5)));
+    assert_eq!(toks[2], Token::new(ASSIGN, "=", Span::new(10, 11, 1, 10)));
+    assert_eq!(toks[3].lexeme, "\"naïve ☃\"");
+    assert_eq!(toks[3].literal, Some(Literal::LitString(String::from("naïve ☃"))));
+    assert_eq!(toks[3].span, Span::new(12, 24, 1, 12));
+    assert_eq!(toks[4], Token::new(SEMICOLON, ";", Span::new(24, 25, 1, 21)));
+    assert_eq!(toks[5], Token::new(IDENTIFIER, "π", Span::new(32, 34, 2, 1)));
+
+    let errors = tokenize(&String::from("x ≠ ٣;")).unwrap_err();
+    assert_eq!(errors[0].to_string(), "[line 1, column 3] Error: Unexpected character '≠'.");
+    assert_eq!(errors[0].span, Span::new(2, 5, 1, 3));
+    // Only ASCII digits make numbers
+    assert_eq!(errors[1].to_string(), "[line 1, column 5] Error: Unexpected character '٣'.");
+}
+
+#[test]
+fn test_lossless() {
+    let src = String::from("x = 1;   // one\n// two\n\n  print x;\t\n// end");
+    let toks = tokenize_lossless(&src).unwrap();
+    let texts = |trivia : &[Token]| -> Vec<String> { trivia.iter().map(|tok| tok.lexeme.clone()).collect() };
+    assert_eq!(toks[3].lexeme, ";");
+    assert_eq!(texts(&toks[3].trailing), vec!["   ", "// one", "\n"]);
+    assert_eq!(toks[4].lexeme, "print");
+    assert_eq!(texts(&toks[4].leading), vec!["// two", "\n", "\n", "  "]);
+    assert_eq!(texts(&toks[6].trailing), vec!["\t\n"]);
+    assert_eq!(toks[7].toktype, EOF);
+    assert_eq!(texts(&toks[7].leading), vec!["// end"]);
+    // Trivia doesn't change the tokens themselves
+    assert_eq!((toks[4].toktype, toks[4].span), (PRINT, Span::new(26, 31, 4, 3)));
+    assert_eq!(untokenize(&toks), src);
+
+    // Ordinary scanning leaves trivia off
+    assert!(tokenize(&src).unwrap().iter().all(|tok| tok.leading.is_empty() && tok.trailing.is_empty()));
+}
+
+#[test]
+fn test_lossless_round_trip() {
+    for src in ["", "   ", "\n\n", "// only a comment", "print \"a b\" ;\r\n",
+                "{\n\tvar café = 1.5; // ☃\n}\n\n", "1\n  +\n  2\n"] {
+    let src = String::from(src);
+    assert_eq!(untokenize(&tokenize_lossless(&src).unwrap()), src);
+    }
+    // Bad input isn't lost either, it's kept as trivia
+    let scanner = Scanner::lossless("a @ b");
+    let toks : Tokens = scanner.filter_map(Result::ok).collect();
+    assert_eq!(untokenize(&toks), "a @ b");
+    assert_eq!(toks[1].leading()[0].toktype, ERROR);
+    assert_eq!(toks[1].leading()[0].lexeme, "@");
+    let scanner = Scanner::lossless("print \"abc;\n");
+    let toks : Tokens = scanner.filter_map(Result::ok).collect();
+    assert_eq!(untokenize(&toks), "print \"abc;\n");
+}
+
+#[test]
+fn test_match_number() {
+    let value = |src : &str| -> f64 {
+    let toks = tokenize(&String::from(src)).unwrap();
+    assert_eq!((toks.len(), toks[0].toktype, toks[0].lexeme.as_str()), (2, NUMBER, src));
+    match toks[0].literal {
+        Some(Literal::LitNumber(value)) => value,
+        _ => panic!("no value for {src}"),
+    }
+    };
+    assert_eq!(value("0"), 0.0);
+    assert_eq!(value("1234.5"), 1234.5);
+    assert_eq!(value("1.5e-3"), 0.0015);
+    assert_eq!(value("2E+2"), 200.0);
+    assert_eq!(value("3e2"), 300.0);
+    assert_eq!(value("1_000_000.000_1"), 1000000.0001);
+    assert_eq!(value("0xff"), 255.0);
+    assert_eq!(value("0XdEaD_bEeF"), 3735928559.0);
+    assert_eq!(value("0b1010_1010"), 170.0);
+    assert_eq!(value("0x20_0000_0000_0000"), 9007199254740992.0);
+
+    // A dot only belongs to the number if a digit follows it
+    let types = |src : &str| -> Vec<TokenType> {
+    tokenize(&String::from(src)).unwrap().iter().map(|tok| tok.toktype).collect()
+    };
+    assert_eq!(types("123.abs()"), vec![NUMBER, DOT, IDENTIFIER, LPAREN, RPAREN, EOF]);
+    assert_eq!(types("123."), vec![NUMBER, DOT, EOF]);
+    assert_eq!(types(".5"), vec![DOT, NUMBER, EOF]);
+    assert_eq!(types("1.2.3"), vec![NUMBER, DOT, NUMBER, EOF]);
+    assert_eq!(types("1-2"), vec![NUMBER, MINUS, NUMBER, EOF]);
+
+    let error = |src : &str| -> String {
+    tokenize(&String::from(src)).unwrap_err()[0].message.clone()
+    };
+    for src in ["1_", "1__0", "1e", "1e+", "1e_5", "0x", "0x_1", "0b", "0b102", "0xfg", "12abc", "1.5e3x"] {
+    assert_eq!(error(src), format!("Malformed number '{src}'."));
+    }
+    assert_eq!(error("1e999"), "Number '1e999' is out of range.");
+    assert_eq!(error("0x20_0000_0000_0001"), "Number '0x20_0000_0000_0001' is out of range.");
+    assert_eq!(error("0xffff_ffff_ffff_ffff_ff"), "Number '0xffff_ffff_ffff_ffff_ff' is out of range.");
+    // An underscore in front is a name, not part of a number
+    assert_eq!(types("_1"), vec![IDENTIFIER, EOF]);
+    assert_eq!(types("1._5"), vec![NUMBER, DOT, IDENTIFIER, EOF]);
+}
+
+#[test]
+fn test_interpolation() {
+    let src = String::from("\"total = ${n * {2}}!\" { }");
+    let toks = tokenize(&src).unwrap();
+    let pieces : Vec<(TokenType, &str)> = toks.iter().map(|tok| (tok.toktype, tok.lexeme.as_str())).collect();
+    assert_eq!(pieces, vec![(INTERPOLATION, "\"total = ${"), (IDENTIFIER, "n"), (STAR, "*"), (LBRACE, "{"),
+                            (NUMBER, "2"), (RBRACE, "}"), (STRINGEND, "}!\""), (LBRACE, "{"), (RBRACE, "}"), (EOF, "")]);
+    assert_eq!(toks[0].literal, Some(Literal::LitString(String::from("total = "))));
+    assert_eq!(toks[6].literal, Some(Literal::LitString(String::from("!"))));
+    assert_eq!(untokenize(&tokenize_lossless(&src).unwrap()), src);
+
+    // Nested, and escaped
+    let toks = tokenize(&String::from("\"a${\"b${c}\"}\\${d}\"")).unwrap();
+    let types : Vec<TokenType> = toks.iter().map(|tok| tok.toktype).collect();
+    assert_eq!(types, vec![INTERPOLATION, INTERPOLATION, IDENTIFIER, STRINGEND, STRINGEND, EOF]);
+    assert_eq!(toks[4].literal, Some(Literal::LitString(String::from("${d}"))));
+    let toks = tokenize(&String::from("\"${a}, ${b}\"")).unwrap();
+    let types : Vec<TokenType> = toks.iter().map(|tok| tok.toktype).collect();
+    assert_eq!(types, vec![INTERPOLATION, IDENTIFIER, STRINGMID, IDENTIFIER, STRINGEND, EOF]);
+}
+
+#[test]
+fn test_block_comments() {
+    let src = String::from("1 /* one */ 2 /* a /* b */\nc */ 3\n/**/4 /*/ x */ 5");
+    let toks = tokenize(&src).unwrap();
+    let lexemes : Vec<&str> = toks.iter().map(|tok| tok.lexeme.as_str()).collect();
+    assert_eq!(lexemes, vec!["1", "2", "3", "4", "5", ""]);
+    // Lines inside a comment still get counted
+    assert_eq!(toks[2].span, Span::new(32, 33, 2, 6));
+    assert_eq!(toks[3].span.line, 3);
+
+    let toks = tokenize_lossless(&src).unwrap();
+    assert_eq!(toks[1].trailing[1].lexeme, "/* a /* b */\nc */");
+    assert_eq!(untokenize(&toks), src);
+
+    // Not closed at the end of the file (the nested one was closed, though)
+    let errors = tokenize(&String::from("x;\n  /* a /* b */ c\n\n")).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].to_string(), "[line 2, column 3] Error: Unterminated block comment.");
+    assert_eq!(errors[0].span, Span::new(5, 21, 2, 3));
+
+    // A lone "*/" is just symbols
+    let toks = tokenize(&String::from("*/")).unwrap();
+    assert_eq!(toks[0].toktype, STAR);
+    assert_eq!(toks[1].toktype, SLASH);
+}
+
+#[test]
+fn test_match_string() {
+    let lit = |value : &str| Literal::LitString(String::from(value));
+    let mut scanner = Scanner::new("\"hello\" world");
+    assert_eq!(scanner.next_token(),
+           Some(Ok(Token::with_literal(STRING, "\"hello\"", lit("hello"), Span::new(0, 7, 1, 1)))));
+
+    let src = r#""tab\there \"quoted\" back\\slash \u{48}\u{1F600}""#;
+    let mut scanner = Scanner::new(src);
+    assert_eq!(scanner.next_token(),
+           Some(Ok(Token::with_literal(STRING, src, lit("tab\there \"quoted\" back\\slash H\u{1F600}"), Span::new(0, 50, 1, 1)))));
+
+    // Strings can span lines.  Line numbers pick up after the string.
+    let toks = tokenize(&String::from("\"one\ntwo\" x")).unwrap();
+    assert_eq!(toks[0].literal, Some(lit("one\ntwo")));
+    assert_eq!(toks[1].span, Span::new(10, 11, 2, 6));
+
+    let errors = tokenize(&String::from("\"bad \\q\" \"bad \\u{110000}\" \"\\u{41\" 1")).unwrap_err();
+    let messages : Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages, vec!["[line 1, column 1] Error: Invalid escape sequence '\\q'.",
+                  "[line 1, column 10] Error: Invalid unicode escape sequence.",
+                  "[line 1, column 27] Error: Invalid unicode escape sequence."]);
+    assert_eq!(errors[2].lexeme, "\"\\u{41\"");
+    assert!(tokenize(&String::from("\"abc\\\"")).is_err());   // Escaped quote doesn't end it
+}
+
+#[test]
+fn test_lex_errors() {
+    let src = String::from("print @ 1;\nvar #x = 123abc;\nprint \"abc;");
+    let errors = tokenize(&src).unwrap_err();
+    let messages : Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages, vec!["[line 1, column 7] Error: Unexpected character '@'.",
+                  "[line 2, column 5] Error: Unexpected character '#'.",
+                  "[line 2, column 10] Error: Malformed number '123abc'.",
+                  "[line 3, column 7] Error: Unterminated string."]);
+    assert_eq!(errors[2].span, Span::new(20, 26, 2, 10));
+
+    // Scanning carries on after a bad character
+    let mut scanner = Scanner::new("1 # 2");
+    scanner.next_token();
+    scanner.next_token();
+    assert!(matches!(scanner.next_token(), Some(Err(_))));
+    scanner.next_token();
+    assert_eq!(scanner.next_token(), Some(Ok(Token::with_literal(NUMBER, "2", Literal::LitNumber(2.0), Span::new(4, 5, 1, 5)))));
+    assert_eq!(scanner.next_token(), None);
 }
 
 }
@@ -286,9 +794,9 @@ fn test_next_token() {
 #[test]
 fn test_smoke() {
     // A basic test just to make sure tests are running
-    let t = Token::new(PLUS, "+", 1);
+    let t = Token::new(PLUS, "+", Span::new(0, 1, 1, 1));
     assert_eq!(t.toktype, PLUS);
     assert_eq!(t.lexeme, "+");
-    assert_eq!(t.line, 1);
-    assert_eq!(t, Token::new(PLUS, "+", 1));
+    assert_eq!(t.span.line, 1);
+    assert_eq!(t, Token::new(PLUS, "+", Span::new(0, 1, 1, 1)));
 }
\ No newline at end of file